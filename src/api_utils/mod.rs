@@ -1,3 +1,3 @@
 pub(crate) mod responses;
-pub(crate) mod structs;
-pub(crate) mod types;
+pub mod structs;
+pub mod types;
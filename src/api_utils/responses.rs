@@ -6,8 +6,52 @@ pub struct ApiResponseMessage {
     pub message: &'static str,
 }
 
+#[derive(Serialize, Clone, Copy)]
+pub struct ApiResponseCooldown {
+    pub message: &'static str,
+    pub retry_after_secs: i64,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ApiResponseConfusable {
+    pub message: &'static str,
+    pub warning: &'static str,
+    pub confusable_with: String,
+}
+
 pub type ApiResponse<T> = (StatusCode, Json<T>);
 
+pub fn request_cooldown(retry_after_secs: i64) -> ApiResponse<ApiResponseCooldown> {
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        Json(ApiResponseCooldown {
+            message: "Request on cooldown",
+            retry_after_secs,
+        }),
+    )
+}
+
+pub fn username_change_cooldown(retry_after_secs: i64) -> ApiResponse<ApiResponseCooldown> {
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        Json(ApiResponseCooldown {
+            message: "Username change on cooldown",
+            retry_after_secs,
+        }),
+    )
+}
+
+pub fn request_created_confusable(confusable_with: String) -> ApiResponse<ApiResponseConfusable> {
+    (
+        StatusCode::OK,
+        Json(ApiResponseConfusable {
+            message: "Request created",
+            warning: "Username looks like one of your friends",
+            confusable_with,
+        }),
+    )
+}
+
 pub static FLUVIO_ERROR: ApiResponse<ApiResponseMessage> = (
     StatusCode::INTERNAL_SERVER_ERROR,
     Json(ApiResponseMessage {
@@ -43,6 +87,13 @@ pub static REQUEST_CREATED: ApiResponse<ApiResponseMessage> = (
     }),
 );
 
+pub static FRIENDSHIP_CREATED: ApiResponse<ApiResponseMessage> = (
+    StatusCode::OK,
+    Json(ApiResponseMessage {
+        message: "Mutual request, friendship created",
+    }),
+);
+
 pub static REQUEST_ACCEPTED: ApiResponse<ApiResponseMessage> = (
     StatusCode::OK,
     Json(ApiResponseMessage {
@@ -57,6 +108,13 @@ pub static REQUEST_REJECTED: ApiResponse<ApiResponseMessage> = (
     }),
 );
 
+pub static REQUEST_CANCELLED: ApiResponse<ApiResponseMessage> = (
+    StatusCode::OK,
+    Json(ApiResponseMessage {
+        message: "Request cancelled",
+    }),
+);
+
 pub static REQUEST_DOES_NOT_EXIST: ApiResponse<ApiResponseMessage> = (
     StatusCode::NOT_FOUND,
     Json(ApiResponseMessage {
@@ -78,6 +136,20 @@ pub static REQUEST_NOT_PENDING: ApiResponse<ApiResponseMessage> = (
     }),
 );
 
+pub static FRIENDSHIP_REMOVED: ApiResponse<ApiResponseMessage> = (
+    StatusCode::OK,
+    Json(ApiResponseMessage {
+        message: "Friendship removed",
+    }),
+);
+
+pub static FRIENDSHIP_DOES_NOT_EXIST: ApiResponse<ApiResponseMessage> = (
+    StatusCode::NOT_FOUND,
+    Json(ApiResponseMessage {
+        message: "Friendship does not exist",
+    }),
+);
+
 pub static BLOCK_ADDED: ApiResponse<ApiResponseMessage> = (
     StatusCode::OK,
     Json(ApiResponseMessage {
@@ -105,3 +177,115 @@ pub static BLOCK_DOES_NOT_EXISTS: ApiResponse<ApiResponseMessage> = (
         message: "Block does not exist",
     }),
 );
+
+pub static INVALID_DISPLAY_NAME: ApiResponse<ApiResponseMessage> = (
+    StatusCode::BAD_REQUEST,
+    Json(ApiResponseMessage {
+        message: "Display name must be at most 32 printable characters",
+    }),
+);
+
+pub static INVALID_BIO: ApiResponse<ApiResponseMessage> = (
+    StatusCode::BAD_REQUEST,
+    Json(ApiResponseMessage {
+        message: "Bio must be at most 300 characters",
+    }),
+);
+
+pub static INVALID_AVATAR: ApiResponse<ApiResponseMessage> = (
+    StatusCode::BAD_REQUEST,
+    Json(ApiResponseMessage {
+        message: "Avatar must be an https url",
+    }),
+);
+
+pub static INVALID_BANNER: ApiResponse<ApiResponseMessage> = (
+    StatusCode::BAD_REQUEST,
+    Json(ApiResponseMessage {
+        message: "Banner must be an https url",
+    }),
+);
+
+pub static INVALID_COUNTRY: ApiResponse<ApiResponseMessage> = (
+    StatusCode::BAD_REQUEST,
+    Json(ApiResponseMessage {
+        message: "Country must be an ISO 3166-1 alpha-2 code",
+    }),
+);
+
+pub static INVALID_USERNAME: ApiResponse<ApiResponseMessage> = (
+    StatusCode::BAD_REQUEST,
+    Json(ApiResponseMessage {
+        message: "Username must be 3 to 32 letters, digits, '_', '-' or '.'",
+    }),
+);
+
+pub static USERNAME_RESERVED: ApiResponse<ApiResponseMessage> = (
+    StatusCode::FORBIDDEN,
+    Json(ApiResponseMessage {
+        message: "Username is reserved",
+    }),
+);
+
+pub static USERNAME_TAKEN: ApiResponse<ApiResponseMessage> = (
+    StatusCode::CONFLICT,
+    Json(ApiResponseMessage {
+        message: "Username already taken",
+    }),
+);
+
+pub static USERNAME_CONFUSABLE: ApiResponse<ApiResponseMessage> = (
+    StatusCode::CONFLICT,
+    Json(ApiResponseMessage {
+        message: "Username looks too much like an existing one",
+    }),
+);
+
+pub static USERNAME_ON_HOLD: ApiResponse<ApiResponseMessage> = (
+    StatusCode::CONFLICT,
+    Json(ApiResponseMessage {
+        message: "Username was recently released and is on hold",
+    }),
+);
+
+pub static SETTINGS_UPDATED: ApiResponse<ApiResponseMessage> = (
+    StatusCode::OK,
+    Json(ApiResponseMessage {
+        message: "Settings updated",
+    }),
+);
+
+pub static FRIEND_REQUESTS_NOT_ALLOWED: ApiResponse<ApiResponseMessage> = (
+    StatusCode::FORBIDDEN,
+    Json(ApiResponseMessage {
+        message: "User does not accept friend requests from you",
+    }),
+);
+
+pub static FRIENDS_PRIVATE: ApiResponse<ApiResponseMessage> = (
+    StatusCode::FORBIDDEN,
+    Json(ApiResponseMessage {
+        message: "Friend list is only visible to friends",
+    }),
+);
+
+pub static PATH_NOT_FOUND: ApiResponse<ApiResponseMessage> = (
+    StatusCode::NOT_FOUND,
+    Json(ApiResponseMessage {
+        message: "No friendship path found within the maximum depth",
+    }),
+);
+
+pub static PATH_SEARCH_TOO_LARGE: ApiResponse<ApiResponseMessage> = (
+    StatusCode::UNPROCESSABLE_ENTITY,
+    Json(ApiResponseMessage {
+        message: "Friendship path search stopped at its limit, try a lower max_depth",
+    }),
+);
+
+pub static PROFILE_PRIVATE: ApiResponse<ApiResponseMessage> = (
+    StatusCode::FORBIDDEN,
+    Json(ApiResponseMessage {
+        message: "Profile is only visible to friends",
+    }),
+);
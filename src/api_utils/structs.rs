@@ -6,9 +6,14 @@ use sqlx::prelude::FromRow;
 
 use crate::api_utils::types::{UserID, UserUsername};
 
-#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum RequestUpdateProfileEnum {
     Username,
+    DisplayName,
+    Bio,
+    Avatar,
+    Banner,
+    Country,
 }
 
 #[derive(FromRow, Debug, Default, Deserialize, Serialize)]
@@ -34,12 +39,157 @@ pub struct PublicUser {
     pub created_at: Option<DateTime<Utc>>,
 }
 
+#[derive(FromRow, Debug, Default, Deserialize, Serialize)]
+pub struct PrivateProfile {
+    pub id: UserID,
+    pub username: UserUsername,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub avatar_url: Option<String>,
+    pub banner_url: Option<String>,
+    pub country: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+/// What anyone can see of a user
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct PublicProfile {
+    pub username: UserUsername,
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub banner_url: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl From<PrivateProfile> for PublicProfile {
+    fn from(profile: PrivateProfile) -> Self {
+        Self {
+            username: profile.username,
+            display_name: profile.display_name,
+            avatar_url: profile.avatar_url,
+            banner_url: profile.banner_url,
+            created_at: profile.created_at,
+        }
+    }
+}
+
+/// What friends see of each other
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct FriendProfile {
+    pub username: UserUsername,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub avatar_url: Option<String>,
+    pub banner_url: Option<String>,
+    pub country: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub friends_since: Option<DateTime<Utc>>,
+}
+
+impl FriendProfile {
+    pub fn new(profile: PrivateProfile, friends_since: Option<DateTime<Utc>>) -> Self {
+        Self {
+            username: profile.username,
+            display_name: profile.display_name,
+            bio: profile.bio,
+            avatar_url: profile.avatar_url,
+            banner_url: profile.banner_url,
+            country: profile.country,
+            created_at: profile.created_at,
+            friends_since,
+        }
+    }
+}
+
+/// What a user sees of itself
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct SelfProfile {
+    pub id: UserID,
+    pub username: UserUsername,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub avatar_url: Option<String>,
+    pub banner_url: Option<String>,
+    pub country: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub username_changed_at: Option<DateTime<Utc>>,
+}
+
+impl SelfProfile {
+    pub fn new(profile: PrivateProfile, username_changed_at: Option<DateTime<Utc>>) -> Self {
+        Self {
+            id: profile.id,
+            username: profile.username,
+            display_name: profile.display_name,
+            bio: profile.bio,
+            avatar_url: profile.avatar_url,
+            banner_url: profile.banner_url,
+            country: profile.country,
+            created_at: profile.created_at,
+            username_changed_at,
+        }
+    }
+}
+
 #[derive(Debug, Default, Deserialize, Serialize)]
 pub enum FriendRequestState {
     #[default]
     Pending,
     Accepted,
     Rejected,
+    Cancelled,
+    Removed,
+}
+
+/// Who may send friend requests to a user
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum FriendRequestPolicy {
+    #[default]
+    Everyone,
+    FriendsOfFriends,
+    Nobody,
+}
+
+/// Who may see a part of a user
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    #[default]
+    Public,
+    FriendsOnly,
+}
+
+#[derive(FromRow, Debug, Default, Serialize, Deserialize)]
+pub struct PrivateSettings {
+    pub friend_requests: String,
+    pub profile_visibility: String,
+    pub friends_visibility: String,
+}
+
+/// Privacy settings, users who never changed them get the defaults
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub struct UserSettings {
+    pub friend_requests: FriendRequestPolicy,
+    pub profile_visibility: Visibility,
+    pub friends_visibility: Visibility,
+}
+
+impl From<PrivateSettings> for UserSettings {
+    fn from(settings: PrivateSettings) -> Self {
+        Self {
+            friend_requests: settings.friend_requests.as_str().into(),
+            profile_visibility: settings.profile_visibility.as_str().into(),
+            friends_visibility: settings.friends_visibility.as_str().into(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct RequestUpdateSettings {
+    pub friend_requests: Option<FriendRequestPolicy>,
+    pub profile_visibility: Option<Visibility>,
+    pub friends_visibility: Option<Visibility>,
 }
 
 #[derive(FromRow, Debug, Default, Serialize, Deserialize)]
@@ -48,6 +198,7 @@ pub struct PrivateFriendRequest {
     pub to_user_id: UserID,
     pub state: String,
     pub created_at: Option<DateTime<Utc>>,
+    pub responded_at: Option<DateTime<Utc>>,
 }
 
 #[derive(FromRow, Debug, Default, Serialize, Deserialize)]
@@ -100,6 +251,32 @@ pub struct RequestFriendships {
     pub to: i64,
 }
 
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RequestFriendSuggestions {
+    pub from: i64,
+    pub to: i64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RequestFriendshipPath {
+    pub username: UserUsername,
+    pub max_depth: Option<usize>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PublicFriendshipPath {
+    /// From the caller to the target, both included
+    pub path: Vec<UserUsername>,
+    pub degrees: usize,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RequestUserFriendships {
+    pub username: UserUsername,
+    pub from: i64,
+    pub to: i64,
+}
+
 #[derive(FromRow, Debug, Default, Serialize, Deserialize)]
 pub struct PrivateFriendship {
     pub from_user_id: UserID,
@@ -113,6 +290,12 @@ pub struct PublicFriendship {
     pub created_at: Option<DateTime<Utc>>,
 }
 
+#[derive(FromRow, Debug, Default, Serialize, Deserialize)]
+pub struct PublicFriendSuggestion {
+    pub username: UserUsername,
+    pub mutual_friends: i64,
+}
+
 #[derive(FromRow, Debug, Default, Deserialize, Serialize)]
 pub struct PrivateBlocked {
     pub from_user_id: UserID,
@@ -123,7 +306,31 @@ pub struct PrivateBlocked {
 #[derive(FromRow, Debug, Default, Deserialize, Serialize)]
 pub struct PublicBlocked {
     pub username: UserUsername,
-    pub created_at: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Default)]
+pub struct OutboxEvent {
+    pub topic: String,
+    pub key: String,
+    pub payload: Vec<u8>,
+}
+
+/// Position of a consumer in a topic, `offset` is the next record to read
+#[derive(Debug, Clone)]
+pub struct ConsumerOffset {
+    pub consumer: String,
+    pub topic: String,
+    pub offset: i64,
+}
+
+#[derive(FromRow, Debug, Default, Clone)]
+pub struct PrivateOutboxEvent {
+    pub id: i64,
+    pub topic: String,
+    pub key: String,
+    pub payload: Vec<u8>,
+    pub attempts: i32,
 }
 
 impl From<&str> for FriendRequestState {
@@ -132,6 +339,8 @@ impl From<&str> for FriendRequestState {
             "pending" => Self::Pending,
             "accepted" => Self::Accepted,
             "rejected" => Self::Rejected,
+            "cancelled" => Self::Cancelled,
+            "removed" => Self::Removed,
             _ => Self::default(),
         }
     }
@@ -143,6 +352,48 @@ impl Display for FriendRequestState {
             FriendRequestState::Pending => write!(f, "pending"),
             FriendRequestState::Accepted => write!(f, "accepted"),
             FriendRequestState::Rejected => write!(f, "rejected"),
+            FriendRequestState::Cancelled => write!(f, "cancelled"),
+            FriendRequestState::Removed => write!(f, "removed"),
+        }
+    }
+}
+
+impl From<&str> for FriendRequestPolicy {
+    fn from(value: &str) -> Self {
+        match value.to_lowercase().as_str() {
+            "everyone" => Self::Everyone,
+            "friends_of_friends" => Self::FriendsOfFriends,
+            "nobody" => Self::Nobody,
+            _ => Self::default(),
+        }
+    }
+}
+
+impl Display for FriendRequestPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FriendRequestPolicy::Everyone => write!(f, "everyone"),
+            FriendRequestPolicy::FriendsOfFriends => write!(f, "friends_of_friends"),
+            FriendRequestPolicy::Nobody => write!(f, "nobody"),
+        }
+    }
+}
+
+impl From<&str> for Visibility {
+    fn from(value: &str) -> Self {
+        match value.to_lowercase().as_str() {
+            "public" => Self::Public,
+            "friends_only" => Self::FriendsOnly,
+            _ => Self::default(),
+        }
+    }
+}
+
+impl Display for Visibility {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Visibility::Public => write!(f, "public"),
+            Visibility::FriendsOnly => write!(f, "friends_only"),
         }
     }
 }
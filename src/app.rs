@@ -3,59 +3,66 @@ use std::{env::var, net::SocketAddr, sync::Arc, time::Duration};
 use axum::{
     Router,
     http::{HeaderValue, Method, header},
+    middleware,
     routing::{get, post},
     serve,
 };
-use fluvio::{Fluvio, FluvioConfig, TopicProducer, metadata::topic::TopicSpec, spu::SpuSocketPool};
 use sqlx::postgres::PgPoolOptions;
 use tokio::net::TcpListener;
 use tower_http::{cors::CorsLayer, trace::TraceLayer};
 use tracing_subscriber::{EnvFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt};
 
 use crate::{
-    fluvio_consumer,
+    api_utils::structs::ConsumerOffset,
+    event_bus::{EventPublisher, EventSubscriber, fluvio::FluvioEventBus, memory::MemoryEventBus},
+    fluvio_consumer::{self, consumer_group},
+    outbox_relay,
+    policy::username::{DEFAULT_RESERVED_USERNAMES, parse_reserved_usernames},
+    provisioning::provision_from_claims,
+    repository::{ConsumerOffsetRepository, Repository, postgres::PgRepository},
     request::{
         block::{block_user, get_blocked, unblock_user},
         friendships::{
-            accept_friend, get_friends, get_request_received, get_request_sent, reject_friend,
-            request_friend,
+            accept_friend, cancel_friend, get_friends, get_friends_of, get_friendship_path,
+            get_mutual_friends, get_request_received, get_request_sent, get_suggestions,
+            reject_friend, remove_friend, request_friend,
         },
-        user::{get_user_info, update_profile},
+        settings::{get_settings, update_settings},
+        user::{get_self_info, get_user_info, update_profile},
     },
-    sql_utils::init::init,
+    sql_utils::migrations::migrate,
 };
 
 #[derive(Clone)]
 pub struct AppState {
-    pub db: sqlx::PgPool,
-    pub request_sent_producer: TopicProducer<SpuSocketPool>,
-    pub request_answered_producer: TopicProducer<SpuSocketPool>,
+    pub repo: Arc<dyn Repository>,
+    pub request_sent_topic: String,
+    pub request_answered_topic: String,
+    pub request_cancelled_topic: String,
+    pub friendship_removed_topic: String,
+    pub user_updated_topic: String,
+    pub friend_request_cooldown_secs: i64,
+    /// Lowercase, NFKC normalized names nobody can take
+    pub reserved_usernames: Vec<String>,
+    pub username_change_cooldown_secs: i64,
+    /// How long a released username stays reserved for its previous owner
+    pub username_hold_secs: i64,
+    pub username_resolved_topic: String,
+    /// Create missing users from the token claims instead of waiting for auth's event
+    pub provision_users_from_claims: bool,
+    pub friendship_path_max_depth: usize,
+    /// Friendships a single path search may read before giving up
+    pub friendship_path_max_friendships: i64,
 }
 
-pub async fn app() -> anyhow::Result<(Router, Fluvio, sqlx::PgPool)> {
-    let origins: Vec<HeaderValue> = var("CORS_ORIGIN")
-        .expect("CORS_ORIGIN env not set")
-        .split(",")
-        .map(|e| e.trim().parse::<HeaderValue>())
-        .collect::<Result<_, _>>()?;
-
-    let cors_layer = CorsLayer::new()
-        .allow_origin(origins)
-        .allow_credentials(true)
-        .allow_methods([Method::GET, Method::POST])
-        .allow_headers([
-            header::CONTENT_TYPE,
-            header::AUTHORIZATION,
-            header::ACCESS_CONTROL_ALLOW_ORIGIN,
-        ]);
-
+fn init_tracing() {
     tracing_subscriber::registry()
         .with(fmt::layer())
         .with(EnvFilter::from_default_env())
         .init();
+}
 
-    let trace_layer = TraceLayer::new_for_http();
-
+async fn connect_db() -> anyhow::Result<sqlx::PgPool> {
     let max_conns: u32 = var("DB_MAX_CONNECTIONS")
         .unwrap_or("1".to_owned())
         .parse()
@@ -76,19 +83,102 @@ pub async fn app() -> anyhow::Result<(Router, Fluvio, sqlx::PgPool)> {
         )
         .await?;
 
-    init(&db).await?;
+    Ok(db)
+}
+
+/// Every route of the service, without the env dependent CORS layer
+pub fn router(state: Arc<AppState>) -> Router {
+    let friendships_router = Router::new()
+        .route("/request", post(request_friend))
+        .route("/accept", post(accept_friend))
+        .route("/reject", post(reject_friend))
+        .route("/cancel", post(cancel_friend))
+        .route("/remove", post(remove_friend))
+        .route("/sent", get(get_request_sent))
+        .route("/received", get(get_request_received))
+        .route("/friends", get(get_friends))
+        .route("/of", get(get_friends_of))
+        .route("/mutual", get(get_mutual_friends))
+        .route("/suggestions", get(get_suggestions))
+        .route("/path", get(get_friendship_path));
+
+    let block_router = Router::new()
+        .route("/block", post(block_user))
+        .route("/unblock", post(unblock_user))
+        .route("/", get(get_blocked));
+
+    let settings_router = Router::new()
+        .route("/update", post(update_settings))
+        .route("/", get(get_settings));
+
+    Router::new()
+        .nest("/friendship", friendships_router)
+        .nest("/blocks", block_router)
+        .nest("/settings", settings_router)
+        .route("/update", post(update_profile))
+        .route("/", get(get_user_info))
+        .route("/me", get(get_self_info))
+        .route(
+            "/health",
+            get(|| async { "Long life to the allmighty turbofish" }),
+        )
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            provision_from_claims,
+        ))
+        .layer(TraceLayer::new_for_http())
+        .with_state(state)
+}
+
+pub type AppParts = (
+    Router,
+    Arc<dyn EventPublisher>,
+    Arc<dyn EventSubscriber>,
+    Arc<dyn Repository>,
+);
+
+pub async fn app() -> anyhow::Result<AppParts> {
+    let origins: Vec<HeaderValue> = var("CORS_ORIGIN")
+        .expect("CORS_ORIGIN env not set")
+        .split(",")
+        .map(|e| e.trim().parse::<HeaderValue>())
+        .collect::<Result<_, _>>()?;
+
+    let cors_layer = CorsLayer::new()
+        .allow_origin(origins)
+        .allow_credentials(true)
+        .allow_methods([Method::GET, Method::POST])
+        .allow_headers([
+            header::CONTENT_TYPE,
+            header::AUTHORIZATION,
+            header::ACCESS_CONTROL_ALLOW_ORIGIN,
+        ]);
 
-    let mut fluvio_config =
-        FluvioConfig::new(var("FLUVIO_ADDR").expect("FLUVIO_ADDR env not set").trim());
-    fluvio_config.use_spu_local_address = true;
+    init_tracing();
 
-    let fluvio = fluvio::Fluvio::connect_with_config(&fluvio_config).await?;
+    let db = connect_db().await?;
+
+    let migrate_on_startup: bool = var("DB_MIGRATE_ON_STARTUP")
+        .unwrap_or("true".to_owned())
+        .parse()
+        .expect("DB_MIGRATE_ON_STARTUP must be a boolean");
+
+    if migrate_on_startup {
+        migrate(&db).await?;
+    }
+
+    let repo: Arc<dyn Repository> = Arc::new(PgRepository::new(db));
 
     let auth_registered_consumer_topic = var("AUTH_REGISTER_TOPIC")
         .unwrap_or("auth-register".to_owned())
         .trim()
         .to_string();
 
+    let auth_deleted_consumer_topic = var("AUTH_DELETE_TOPIC")
+        .unwrap_or("auth-delete".to_owned())
+        .trim()
+        .to_string();
+
     let request_producer_topic = var("USER_RESQUEST_TOPIC")
         .unwrap_or("friendships-request".to_owned())
         .trim()
@@ -99,81 +189,151 @@ pub async fn app() -> anyhow::Result<(Router, Fluvio, sqlx::PgPool)> {
         .trim()
         .to_string();
 
-    let admin = fluvio.admin().await;
+    let cancelled_producer_topic = var("USER_CANCEL_TOPIC")
+        .unwrap_or("friendships-cancel".to_owned())
+        .trim()
+        .to_string();
 
-    let topics = admin
-        .all::<TopicSpec>()
-        .await
-        .expect("Failed to list topics");
-    let topic_names = topics
-        .iter()
-        .map(|topic| topic.name.clone())
-        .collect::<Vec<String>>();
+    let removed_producer_topic = var("USER_REMOVED_TOPIC")
+        .unwrap_or("friendships-removed".to_owned())
+        .trim()
+        .to_string();
 
-    //Creates topic if they dont exist
+    let updated_producer_topic = var("USER_UPDATED_TOPIC")
+        .unwrap_or("user-updated".to_owned())
+        .trim()
+        .to_string();
 
-    if !topic_names.contains(&request_producer_topic) {
-        let topic_spec = TopicSpec::new_computed(1, 1, None);
-        admin
-            .create(request_producer_topic.clone(), false, topic_spec)
-            .await?;
-    }
+    let purged_producer_topic = var("USER_PURGED_TOPIC")
+        .unwrap_or("user-purged".to_owned())
+        .trim()
+        .to_string();
 
-    if !topic_names.contains(&answered_producer_topic) {
-        let topic_spec = TopicSpec::new_computed(1, 1, None);
-        admin
-            .create(answered_producer_topic.clone(), false, topic_spec)
-            .await?;
-    }
+    let resolved_producer_topic = var("USER_USERNAME_RESOLVED_TOPIC")
+        .unwrap_or("user-username-resolved".to_owned())
+        .trim()
+        .to_string();
 
-    if !topic_names.contains(&auth_registered_consumer_topic) {
-        let topic_spec = TopicSpec::new_computed(1, 1, None);
-        admin
-            .create(auth_registered_consumer_topic.clone(), false, topic_spec)
-            .await?;
-    }
+    let dead_letter_producer_topic = var("CONSUMER_DEAD_LETTER_TOPIC")
+        .unwrap_or("user-service-dead-letter".to_owned())
+        .trim()
+        .to_string();
 
-    let request_producer = fluvio.topic_producer(request_producer_topic).await?;
+    let (publisher, subscriber): (Arc<dyn EventPublisher>, Arc<dyn EventSubscriber>) =
+        match var("EVENT_BUS").unwrap_or("fluvio".to_owned()).trim() {
+            "fluvio" => {
+                let bus = FluvioEventBus::connect(
+                    var("FLUVIO_ADDR").expect("FLUVIO_ADDR env not set").trim(),
+                )
+                .await?;
+
+                bus.ensure_topics(&[
+                    &request_producer_topic,
+                    &answered_producer_topic,
+                    &cancelled_producer_topic,
+                    &removed_producer_topic,
+                    &updated_producer_topic,
+                    &purged_producer_topic,
+                    &resolved_producer_topic,
+                    &dead_letter_producer_topic,
+                    &auth_registered_consumer_topic,
+                    &auth_deleted_consumer_topic,
+                ])
+                .await?;
+
+                let bus = Arc::new(bus);
+                (bus.clone(), bus)
+            }
+            "memory" => {
+                let bus = Arc::new(MemoryEventBus::new());
+                (bus.clone(), bus)
+            }
+            other => panic!("EVENT_BUS must be fluvio or memory, got {other}"),
+        };
+
+    let friend_request_cooldown_secs: i64 = var("FRIEND_REQUEST_COOLDOWN_SECS")
+        .unwrap_or("86400".to_owned())
+        .parse()
+        .expect("FRIEND_REQUEST_COOLDOWN_SECS must be a number");
+
+    let reserved_usernames = parse_reserved_usernames(
+        &var("RESERVED_USERNAMES").unwrap_or(DEFAULT_RESERVED_USERNAMES.to_owned()),
+    );
+
+    let username_change_cooldown_secs: i64 = var("USERNAME_CHANGE_COOLDOWN_SECS")
+        .unwrap_or("2592000".to_owned())
+        .parse()
+        .expect("USERNAME_CHANGE_COOLDOWN_SECS must be a number");
 
-    let answered_producer = fluvio.topic_producer(answered_producer_topic).await?;
+    let username_hold_secs: i64 = var("USERNAME_HOLD_SECS")
+        .unwrap_or("7776000".to_owned())
+        .parse()
+        .expect("USERNAME_HOLD_SECS must be a number");
+
+    let provision_users_from_claims: bool = var("USER_PROVISION_FROM_CLAIMS")
+        .unwrap_or("false".to_owned())
+        .parse()
+        .expect("USER_PROVISION_FROM_CLAIMS must be a boolean");
+
+    let friendship_path_max_depth: usize = var("FRIENDSHIP_PATH_MAX_DEPTH")
+        .unwrap_or("6".to_owned())
+        .parse()
+        .expect("FRIENDSHIP_PATH_MAX_DEPTH must be a number");
+
+    let friendship_path_max_friendships: i64 = var("FRIENDSHIP_PATH_MAX_FRIENDSHIPS")
+        .unwrap_or("10000".to_owned())
+        .parse()
+        .expect("FRIENDSHIP_PATH_MAX_FRIENDSHIPS must be a number");
 
     let state = Arc::new(AppState {
-        db: db.clone(),
-        request_sent_producer: request_producer,
-        request_answered_producer: answered_producer,
+        repo: repo.clone(),
+        request_sent_topic: request_producer_topic,
+        request_answered_topic: answered_producer_topic,
+        request_cancelled_topic: cancelled_producer_topic,
+        friendship_removed_topic: removed_producer_topic,
+        user_updated_topic: updated_producer_topic,
+        friend_request_cooldown_secs,
+        reserved_usernames,
+        username_change_cooldown_secs,
+        username_hold_secs,
+        username_resolved_topic: resolved_producer_topic,
+        provision_users_from_claims,
+        friendship_path_max_depth,
+        friendship_path_max_friendships,
     });
 
-    let friendships_router = Router::new()
-        .route("/request", post(request_friend))
-        .route("/accept", post(accept_friend))
-        .route("/reject", post(reject_friend))
-        .route("/sent", get(get_request_sent))
-        .route("/received", get(get_request_received))
-        .route("/friends", get(get_friends));
+    let app = router(state).layer(cors_layer);
 
-    let block_router = Router::new()
-        .route("/block", post(block_user))
-        .route("/unblock", post(unblock_user))
-        .route("/", get(get_blocked));
+    Ok((app, publisher, subscriber, repo))
+}
 
-    let app = Router::new()
-        .nest("/friendship", friendships_router)
-        .nest("/blocks", block_router)
-        .route("/update", post(update_profile))
-        .route("/", get(get_user_info))
-        .route(
-            "/health",
-            get(|| async { "Long life to the allmighty turbofish" }),
-        )
-        .layer(cors_layer)
-        .layer(trace_layer)
-        .with_state(state);
+/// Applies pending schema migrations without starting the server
+pub async fn run_migrations() -> anyhow::Result<()> {
+    init_tracing();
+
+    let db = connect_db().await?;
+    migrate(&db).await
+}
+
+/// Moves the consumer back to `offset` of `topic`, the next start replays it from there
+pub async fn replay_topic(topic: &str, offset: i64) -> anyhow::Result<()> {
+    init_tracing();
 
-    Ok((app, fluvio, db))
+    let repo = PgRepository::new(connect_db().await?);
+    repo.commit_consumer_offset(ConsumerOffset {
+        consumer: consumer_group(),
+        topic: topic.to_owned(),
+        offset,
+    })
+    .await?;
+
+    tracing::info!("{topic} will be replayed from offset {offset} on the next start");
+
+    Ok(())
 }
 
 pub async fn run() -> anyhow::Result<()> {
-    let (app, fluvio, db) = app().await?;
+    let (app, publisher, subscriber, repo) = app().await?;
 
     let addr: SocketAddr = var("SOCKET_ADDR")
         .expect("SOCKET_ADDR env not set")
@@ -182,9 +342,11 @@ pub async fn run() -> anyhow::Result<()> {
 
     println!("Server runnnig at: {addr}");
 
-    let consumer_thread = tokio::spawn(fluvio_consumer::run(fluvio, db));
+    let relay_thread = tokio::spawn(outbox_relay::run(publisher, repo.clone()));
+    let consumer_thread = tokio::spawn(fluvio_consumer::run(subscriber, repo));
 
     serve(listener, app.into_make_service()).await?;
     consumer_thread.await??;
+    relay_thread.await??;
     Ok(())
 }
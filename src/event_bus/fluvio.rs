@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use fluvio::{
+    Fluvio, FluvioConfig, Offset, TopicProducer, consumer::ConsumerConfigExtBuilder,
+    metadata::topic::TopicSpec, spu::SpuSocketPool,
+};
+use futures::StreamExt;
+use tokio::sync::Mutex;
+
+use crate::event_bus::{Event, EventPublisher, EventStream, EventSubscriber};
+
+pub struct FluvioEventBus {
+    fluvio: Fluvio,
+    producers: Mutex<HashMap<String, TopicProducer<SpuSocketPool>>>,
+}
+
+impl FluvioEventBus {
+    pub async fn connect(addr: &str) -> anyhow::Result<Self> {
+        let mut fluvio_config = FluvioConfig::new(addr);
+        fluvio_config.use_spu_local_address = true;
+
+        let fluvio = Fluvio::connect_with_config(&fluvio_config).await?;
+
+        Ok(Self {
+            fluvio,
+            producers: Mutex::new(HashMap::new()),
+        })
+    }
+
+    //Creates topics if they dont exist
+    pub async fn ensure_topics(&self, topics: &[&str]) -> anyhow::Result<()> {
+        let admin = self.fluvio.admin().await;
+
+        let existing = admin
+            .all::<TopicSpec>()
+            .await
+            .expect("Failed to list topics");
+        let existing_names = existing
+            .iter()
+            .map(|topic| topic.name.as_str())
+            .collect::<Vec<&str>>();
+
+        for topic in topics {
+            if !existing_names.contains(topic) {
+                let topic_spec = TopicSpec::new_computed(1, 1, None);
+                admin.create(topic.to_string(), false, topic_spec).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EventPublisher for FluvioEventBus {
+    async fn publish(&self, topic: &str, key: &str, payload: &[u8]) -> anyhow::Result<()> {
+        let mut producers = self.producers.lock().await;
+
+        if !producers.contains_key(topic) {
+            let producer = self.fluvio.topic_producer(topic).await?;
+            producers.insert(topic.to_owned(), producer);
+        }
+
+        producers[topic]
+            .send(key.to_owned(), payload.to_vec())
+            .await?
+            .wait()
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EventSubscriber for FluvioEventBus {
+    async fn subscribe(&self, topic: &str, offset: i64) -> anyhow::Result<EventStream> {
+        let consumer_config = ConsumerConfigExtBuilder::default()
+            .topic(topic)
+            .offset_start(Offset::absolute(offset)?)
+            .build()?;
+
+        let consumer_stream = self.fluvio.consumer_with_config(consumer_config).await?;
+
+        let events = consumer_stream.map(|record| {
+            let record = record?;
+            Ok(Event {
+                key: record.key().map(|key| key.to_vec()),
+                payload: record.value().to_vec(),
+                offset: record.offset(),
+            })
+        });
+
+        Ok(Box::pin(events))
+    }
+}
@@ -0,0 +1,103 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use tokio::sync::watch;
+
+use crate::event_bus::{Event, EventPublisher, EventStream, EventSubscriber};
+
+struct Topic {
+    events: Vec<Event>,
+    published: watch::Sender<usize>,
+}
+
+impl Default for Topic {
+    fn default() -> Self {
+        Self {
+            events: Vec::new(),
+            published: watch::channel(0).0,
+        }
+    }
+}
+
+/// In process event bus, events are kept for the lifetime of the bus so late
+/// subscribers can start from any offset, same as a fluvio consumer
+#[derive(Clone, Default)]
+pub struct MemoryEventBus {
+    topics: Arc<Mutex<HashMap<String, Topic>>>,
+}
+
+impl MemoryEventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every event published on the topic so far
+    pub fn events(&self, topic: &str) -> Vec<Event> {
+        let topics = self.topics.lock().expect("Event bus lock poisoned");
+
+        topics
+            .get(topic)
+            .map(|topic| topic.events.clone())
+            .unwrap_or_default()
+    }
+}
+
+#[async_trait]
+impl EventPublisher for MemoryEventBus {
+    async fn publish(&self, topic: &str, key: &str, payload: &[u8]) -> anyhow::Result<()> {
+        let mut topics = self.topics.lock().expect("Event bus lock poisoned");
+        let topic = topics.entry(topic.to_owned()).or_default();
+
+        let offset = topic.events.len();
+        topic.events.push(Event {
+            key: Some(key.as_bytes().to_vec()),
+            payload: payload.to_vec(),
+            offset: offset as i64,
+        });
+        topic.published.send_replace(offset + 1);
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EventSubscriber for MemoryEventBus {
+    async fn subscribe(&self, topic: &str, offset: i64) -> anyhow::Result<EventStream> {
+        let published = {
+            let mut topics = self.topics.lock().expect("Event bus lock poisoned");
+            topics
+                .entry(topic.to_owned())
+                .or_default()
+                .published
+                .subscribe()
+        };
+
+        let state = (
+            self.clone(),
+            topic.to_owned(),
+            offset.max(0) as usize,
+            published,
+        );
+
+        let events =
+            futures::stream::unfold(state, |(bus, topic, next, mut published)| async move {
+                loop {
+                    let event = {
+                        let topics = bus.topics.lock().expect("Event bus lock poisoned");
+                        topics.get(&topic).and_then(|t| t.events.get(next).cloned())
+                    };
+
+                    if let Some(event) = event {
+                        return Some((Ok(event), (bus, topic, next + 1, published)));
+                    }
+
+                    published.changed().await.ok()?;
+                }
+            });
+
+        Ok(Box::pin(events))
+    }
+}
@@ -0,0 +1,28 @@
+use std::pin::Pin;
+
+use async_trait::async_trait;
+use futures::Stream;
+
+pub mod fluvio;
+pub mod memory;
+
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub key: Option<Vec<u8>>,
+    pub payload: Vec<u8>,
+    pub offset: i64,
+}
+
+pub type EventStream = Pin<Box<dyn Stream<Item = anyhow::Result<Event>> + Send>>;
+
+#[async_trait]
+pub trait EventPublisher: Send + Sync {
+    /// Resolves once the event has been acknowledged by the backend
+    async fn publish(&self, topic: &str, key: &str, payload: &[u8]) -> anyhow::Result<()>;
+}
+
+#[async_trait]
+pub trait EventSubscriber: Send + Sync {
+    /// Streams every event of the topic starting at `offset`, 0 being the oldest one
+    async fn subscribe(&self, topic: &str, offset: i64) -> anyhow::Result<EventStream>;
+}
@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+
+use crate::api_utils::structs::RequestUpdateProfileEnum;
+
+// Events this service produces or consumes that are not part of topic_structs yet
+
+/// Sent by auth once an account is deleted
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserDeleted {
+    pub id: String,
+}
+
+/// A consumed record that could not be applied, published on the dead letter topic
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub topic: String,
+    pub offset: i64,
+    pub key: Option<String>,
+    pub payload: String,
+    pub reason: String,
+}
+
+/// Sent back to auth when the requested username was taken here and another one was assigned
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UsernameCollisionResolved {
+    pub user_id: String,
+    pub requested_username: String,
+    pub assigned_username: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserPurged {
+    pub user_id: String,
+    pub username: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FriendshipRemoved {
+    pub from_username: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FriendRequestCancelled {
+    pub from_username: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProfileFieldChange {
+    pub field: RequestUpdateProfileEnum,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+/// Only lists the fields whose value actually changed
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserUpdated {
+    pub user_id: String,
+    pub changes: Vec<ProfileFieldChange>,
+}
@@ -1,48 +1,980 @@
-use async_std::stream::StreamExt;
+use std::{sync::Arc, time::Duration};
+
 use dotenvy::var;
-use fluvio::{Fluvio, Offset, consumer::ConsumerConfigExtBuilder};
-use serde_json::from_slice;
+use futures::StreamExt;
+use serde_json::{from_slice, to_vec};
 use topic_structs::UserCreated;
 
 use crate::{
-    api_utils::structs::PrivateUser,
-    sql_utils::calls::{get_public_user, insert_user},
+    api_utils::structs::{ConsumerOffset, OutboxEvent},
+    event_bus::{Event, EventSubscriber},
+    events::{DeadLetter, UserDeleted, UserPurged},
+    policy::username::{DEFAULT_RESERVED_USERNAMES, parse_reserved_usernames},
+    provisioning::{UsernameRules, prepare_user},
+    repository::{Repository, is_transient},
 };
 
-pub async fn run(fluvio: Fluvio, db: sqlx::PgPool) -> anyhow::Result<()> {
+// Longest wait between two attempts to resubscribe after the stream failed
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// Name offsets are committed under, replicas sharing it resume from the same place
+pub fn consumer_group() -> String {
+    var("CONSUMER_GROUP")
+        .unwrap_or("user-service".to_owned())
+        .trim()
+        .to_string()
+}
+
+struct ConsumerConfig {
+    dead_letter_topic: String,
+    max_retries: u32,
+    retry_base_delay: Duration,
+    // Longest wait between two attempts at the same record
+    retry_max_delay: Duration,
+}
+
+enum Handler {
+    Registered {
+        resolved_topic: String,
+        reserved_usernames: Vec<String>,
+        username_hold_secs: i64,
+    },
+    Deleted {
+        purged_topic: String,
+    },
+}
+
+enum Outcome {
+    // The handler committed the offset along with its own writes
+    Applied(&'static str),
+    // Nothing to write, the offset still has to be committed
+    Skipped(&'static str),
+}
+
+enum HandleError {
+    Malformed(String),
+    Rejected(String),
+    Db(anyhow::Error),
+}
+
+pub async fn run(
+    subscriber: Arc<dyn EventSubscriber>,
+    repo: Arc<dyn Repository>,
+) -> anyhow::Result<()> {
     //TODO! do a proper fix on this
     let auth_registered_consumer_topic = var("AUTH_REGISTER_TOPIC")
         .unwrap_or("auth-register".to_owned())
         .trim()
         .to_string();
 
-    let consumer_config = ConsumerConfigExtBuilder::default()
-        .topic(auth_registered_consumer_topic)
-        .offset_start(Offset::beginning())
-        .build()
-        .expect("Failed to build consumer config");
+    let auth_deleted_consumer_topic = var("AUTH_DELETE_TOPIC")
+        .unwrap_or("auth-delete".to_owned())
+        .trim()
+        .to_string();
+
+    let purged_producer_topic = var("USER_PURGED_TOPIC")
+        .unwrap_or("user-purged".to_owned())
+        .trim()
+        .to_string();
+
+    let resolved_producer_topic = var("USER_USERNAME_RESOLVED_TOPIC")
+        .unwrap_or("user-username-resolved".to_owned())
+        .trim()
+        .to_string();
+
+    // Same settings the API checks renames against
+    let reserved_usernames = parse_reserved_usernames(
+        &var("RESERVED_USERNAMES").unwrap_or(DEFAULT_RESERVED_USERNAMES.to_owned()),
+    );
+
+    let username_hold_secs: i64 = var("USERNAME_HOLD_SECS")
+        .unwrap_or("7776000".to_owned())
+        .parse()
+        .expect("USERNAME_HOLD_SECS must be a number");
+
+    let dead_letter_topic = var("CONSUMER_DEAD_LETTER_TOPIC")
+        .unwrap_or("user-service-dead-letter".to_owned())
+        .trim()
+        .to_string();
+
+    let max_retries: u32 = var("CONSUMER_MAX_RETRIES")
+        .unwrap_or("5".to_owned())
+        .parse()
+        .expect("CONSUMER_MAX_RETRIES must be a number");
+
+    let retry_base_delay: u64 = var("CONSUMER_RETRY_BASE_MS")
+        .unwrap_or("200".to_owned())
+        .parse()
+        .expect("CONSUMER_RETRY_BASE_MS must be a number");
+
+    let retry_max_delay: u64 = var("CONSUMER_RETRY_MAX_MS")
+        .unwrap_or("10000".to_owned())
+        .parse()
+        .expect("CONSUMER_RETRY_MAX_MS must be a number");
+
+    let config = Arc::new(ConsumerConfig {
+        dead_letter_topic,
+        max_retries,
+        retry_base_delay: Duration::from_millis(retry_base_delay),
+        retry_max_delay: Duration::from_millis(retry_max_delay),
+    });
+
+    tokio::join!(
+        consume(
+            subscriber.clone(),
+            repo.clone(),
+            config.clone(),
+            auth_registered_consumer_topic,
+            Handler::Registered {
+                resolved_topic: resolved_producer_topic,
+                reserved_usernames,
+                username_hold_secs,
+            },
+        ),
+        consume(
+            subscriber,
+            repo,
+            config,
+            auth_deleted_consumer_topic,
+            Handler::Deleted {
+                purged_topic: purged_producer_topic,
+            },
+        ),
+    );
+
+    Ok(())
+}
+
+// Where the last run stopped, a topic never consumed starts at the beginning.
+// Starting anywhere else on a failed lookup would replay or skip records, so it waits instead
+async fn committed_offset(
+    repo: &dyn Repository,
+    config: &ConsumerConfig,
+    topic: &str,
+) -> ConsumerOffset {
+    let consumer = consumer_group();
+    let mut delay = config.retry_base_delay;
+
+    let offset = loop {
+        match repo.get_consumer_offset(&consumer, topic).await {
+            Ok(offset) => break offset.unwrap_or(0),
+            Err(e) => {
+                tracing::warn!(
+                    "Failed to read the offset of {topic}, retrying in {delay:?}: {e:?}"
+                );
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+            }
+        }
+    };
+
+    ConsumerOffset {
+        consumer,
+        topic: topic.to_owned(),
+        offset,
+    }
+}
+
+// Never returns, a failed stream is resubscribed from the last committed offset
+async fn consume(
+    subscriber: Arc<dyn EventSubscriber>,
+    repo: Arc<dyn Repository>,
+    config: Arc<ConsumerConfig>,
+    topic: String,
+    handler: Handler,
+) {
+    let mut reconnect_delay = config.retry_base_delay;
+
+    loop {
+        let position = committed_offset(repo.as_ref(), &config, &topic).await;
+
+        match subscriber.subscribe(&topic, position.offset).await {
+            Ok(mut consumer_stream) => {
+                tracing::info!("Consuming {topic} from offset {}", position.offset);
+
+                while let Some(record) = consumer_stream.next().await {
+                    match record {
+                        Ok(record) => {
+                            let offset = record.offset;
+
+                            // Later records would commit past this one, read it again instead
+                            if let Err(e) =
+                                process(repo.as_ref(), &config, &handler, &position, record).await
+                            {
+                                tracing::error!(
+                                    "Failed to dead letter record {offset} of {topic}: {e:?}"
+                                );
+                                break;
+                            }
+                            reconnect_delay = config.retry_base_delay;
+                        }
+                        Err(e) => {
+                            tracing::warn!("Consumer stream of {topic} failed: {e:?}");
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(e) => tracing::warn!("Failed to subscribe to {topic}: {e:?}"),
+        }
+
+        tracing::warn!("Resubscribing to {topic} in {reconnect_delay:?}");
+        tokio::time::sleep(reconnect_delay).await;
+        reconnect_delay = (reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+// Fails when the record could neither be applied nor dead lettered
+async fn process(
+    repo: &dyn Repository,
+    config: &ConsumerConfig,
+    handler: &Handler,
+    position: &ConsumerOffset,
+    record: Event,
+) -> anyhow::Result<()> {
+    let offset = ConsumerOffset {
+        offset: record.offset + 1,
+        ..position.clone()
+    };
+
+    let mut attempt = 0;
+
+    loop {
+        let result = match handler {
+            Handler::Registered {
+                resolved_topic,
+                reserved_usernames,
+                username_hold_secs,
+            } => {
+                let rules = UsernameRules {
+                    reserved: reserved_usernames,
+                    hold_secs: *username_hold_secs,
+                };
+
+                handle_registered(repo, &record, offset.clone(), &rules, resolved_topic).await
+            }
+            Handler::Deleted { purged_topic } => {
+                handle_deleted(repo, &record, offset.clone(), purged_topic).await
+            }
+        };
+
+        let reason = match result {
+            Ok(Outcome::Applied(outcome)) => {
+                tracing::info!(
+                    topic = %offset.topic,
+                    offset = record.offset,
+                    outcome,
+                    "Consumed record"
+                );
+                return Ok(());
+            }
+            Ok(Outcome::Skipped(outcome)) => {
+                tracing::info!(
+                    topic = %offset.topic,
+                    offset = record.offset,
+                    outcome,
+                    "Consumed record"
+                );
+
+                // A later commit covers this one, the record changes nothing if read again
+                if let Err(e) = repo.commit_consumer_offset(offset).await {
+                    tracing::error!("Failed to commit consumer offset: {e:?}");
+                }
+                return Ok(());
+            }
+            Err(HandleError::Db(e)) if is_transient(&e) && attempt < config.max_retries => {
+                attempt += 1;
+                let factor = 2u32.checked_pow(attempt - 1).unwrap_or(u32::MAX);
+                let delay = config
+                    .retry_base_delay
+                    .saturating_mul(factor)
+                    .min(config.retry_max_delay);
+
+                tracing::warn!(
+                    topic = %offset.topic,
+                    offset = record.offset,
+                    outcome = "retried",
+                    "Transient error, attempt {attempt} of {} in {delay:?}: {e:?}",
+                    config.max_retries
+                );
+                tokio::time::sleep(delay).await;
+                continue;
+            }
+            Err(HandleError::Malformed(reason)) => format!("Malformed payload: {reason}"),
+            Err(HandleError::Rejected(reason)) => reason,
+            Err(HandleError::Db(e)) => format!("Database error: {e}"),
+        };
+
+        return dead_letter(repo, config, &record, offset, reason).await;
+    }
+}
+
+async fn dead_letter(
+    repo: &dyn Repository,
+    config: &ConsumerConfig,
+    record: &Event,
+    offset: ConsumerOffset,
+    reason: String,
+) -> anyhow::Result<()> {
+    let key = record
+        .key
+        .as_ref()
+        .map(|key| String::from_utf8_lossy(key).into_owned());
+
+    let letter = DeadLetter {
+        topic: offset.topic.clone(),
+        offset: record.offset,
+        key: key.clone(),
+        payload: String::from_utf8_lossy(&record.payload).into_owned(),
+        reason,
+    };
+
+    tracing::warn!(
+        topic = %letter.topic,
+        offset = letter.offset,
+        outcome = "dead_lettered",
+        "Record dead lettered: {}",
+        letter.reason
+    );
+
+    let letter_bytes = to_vec(&letter)?;
+
+    let event = OutboxEvent {
+        topic: config.dead_letter_topic.clone(),
+        key: key.unwrap_or_default(),
+        payload: letter_bytes,
+    };
+
+    repo.dead_letter(event, offset).await
+}
+
+async fn handle_registered(
+    repo: &dyn Repository,
+    record: &Event,
+    offset: ConsumerOffset,
+    rules: &UsernameRules<'_>,
+    resolved_topic: &str,
+) -> Result<Outcome, HandleError> {
+    let user_created = from_slice::<UserCreated>(&record.payload)
+        .map_err(|e| HandleError::Malformed(e.to_string()))?;
+
+    // The same registration delivered again, the name may differ after a collision or a rename
+    let existing = repo
+        .find_public_user(&user_created.id)
+        .await
+        .map_err(HandleError::Db)?;
+
+    if existing.is_some() {
+        return Ok(Outcome::Skipped("duplicate"));
+    }
+
+    // The deletion was consumed first, the user stays gone
+    let purged = repo
+        .is_user_purged(&user_created.id)
+        .await
+        .map_err(HandleError::Db)?;
+
+    if purged {
+        return Ok(Outcome::Skipped("purged"));
+    }
+
+    let (user, event) = prepare_user(
+        repo,
+        &user_created.id,
+        &user_created.username,
+        rules,
+        resolved_topic,
+    )
+    .await
+    .map_err(|e| HandleError::Rejected(format!("Failed to encode: {e}")))?;
+
+    let outcome = if event.is_some() {
+        "inserted_renamed"
+    } else {
+        "inserted"
+    };
+
+    if let Err(e) = repo.insert_user(user, event, Some(offset)).await {
+        // Provisioning from token claims created the same user in the meantime
+        if let Ok(Some(_)) = repo.find_public_user(&user_created.id).await {
+            return Ok(Outcome::Skipped("duplicate"));
+        }
+
+        return Err(HandleError::Db(e));
+    }
+
+    Ok(Outcome::Applied(outcome))
+}
+
+async fn handle_deleted(
+    repo: &dyn Repository,
+    record: &Event,
+    offset: ConsumerOffset,
+    purged_topic: &str,
+) -> Result<Outcome, HandleError> {
+    let user_deleted = from_slice::<UserDeleted>(&record.payload)
+        .map_err(|e| HandleError::Malformed(e.to_string()))?;
+
+    let existing = repo
+        .find_public_user(&user_deleted.id)
+        .await
+        .map_err(HandleError::Db)?;
+
+    // Either a deletion delivered twice or one consumed before its registration, which the
+    // tombstone keeps from creating the user afterwards
+    let Some(user) = existing else {
+        repo.insert_purged_user(&user_deleted.id, offset)
+            .await
+            .map_err(HandleError::Db)?;
+
+        return Ok(Outcome::Applied("tombstoned"));
+    };
+
+    let purged = UserPurged {
+        user_id: user_deleted.id.clone(),
+        username: user.username,
+    };
+
+    let purged_bytes =
+        to_vec(&purged).map_err(|e| HandleError::Rejected(format!("Failed to encode: {e}")))?;
+
+    let event = OutboxEvent {
+        topic: purged_topic.to_owned(),
+        key: user_deleted.id.clone(),
+        payload: purged_bytes,
+    };
+
+    repo.delete_user(&user_deleted.id, event, Some(offset))
+        .await
+        .map_err(HandleError::Db)?;
+
+    Ok(Outcome::Applied("purged"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use async_trait::async_trait;
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        api_utils::structs::{PrivateUser, RequestUpdateProfileEnum},
+        event_bus::{EventPublisher, EventStream, memory::MemoryEventBus},
+        events::UsernameCollisionResolved,
+        repository::{
+            ConsumerOffsetRepository, OutboxRepository, UserRepository, memory::MemoryRepository,
+        },
+    };
+
+    const DELETE_TOPIC: &str = "auth-delete";
+
+    // Ends the first subscription with an error right after its first record
+    struct FlakySubscriber {
+        bus: MemoryEventBus,
+        subscriptions: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl EventSubscriber for FlakySubscriber {
+        async fn subscribe(&self, topic: &str, offset: i64) -> anyhow::Result<EventStream> {
+            let events = self.bus.subscribe(topic, offset).await?;
+
+            if self.subscriptions.fetch_add(1, Ordering::SeqCst) > 0 {
+                return Ok(events);
+            }
+
+            let failure = futures::stream::once(async { Err(anyhow::anyhow!("Connection lost")) });
+            Ok(Box::pin(events.take(1).chain(failure)))
+        }
+    }
+
+    fn config() -> ConsumerConfig {
+        ConsumerConfig {
+            dead_letter_topic: "dead-letter".to_owned(),
+            max_retries: 3,
+            retry_base_delay: Duration::from_millis(1),
+            retry_max_delay: Duration::from_millis(4),
+        }
+    }
+
+    fn deleted() -> Handler {
+        Handler::Deleted {
+            purged_topic: "user-purged".to_owned(),
+        }
+    }
 
-    let mut consumer_stream = fluvio.consumer_with_config(consumer_config).await?;
+    fn registered() -> Handler {
+        Handler::Registered {
+            resolved_topic: "user-username-resolved".to_owned(),
+            reserved_usernames: vec!["admin".to_owned()],
+            username_hold_secs: 3600,
+        }
+    }
 
-    while let Some(Ok(record)) = consumer_stream.next().await {
-        let parse_result = from_slice::<UserCreated>(record.value());
+    fn position() -> ConsumerOffset {
+        ConsumerOffset {
+            consumer: consumer_group(),
+            topic: DELETE_TOPIC.to_owned(),
+            offset: 0,
+        }
+    }
 
-        if let Ok(user_created) = &parse_result {
+    fn record(offset: i64, payload: &[u8]) -> Event {
+        Event {
+            key: None,
+            payload: payload.to_vec(),
+            offset,
+        }
+    }
+
+    fn deletion(user_id: &str) -> Vec<u8> {
+        to_vec(&json!({ "id": user_id })).unwrap()
+    }
+
+    async fn repo_with(ids: &[&str]) -> MemoryRepository {
+        let repo = MemoryRepository::new();
+
+        for id in ids {
             let user = PrivateUser {
-                id: user_created.id.clone(),
-                username: user_created.username.clone(),
+                id: id.to_string(),
+                username: id.trim_end_matches("-id").to_owned(),
                 created_at: None,
             };
-            if get_public_user(&user.id, &db).await.is_some() {
-                //TODO! User already exists, big time error
-                continue;
-            }
+            repo.insert_user(user, None, None).await.unwrap();
+        }
+
+        repo
+    }
+
+    async fn committed(repo: &MemoryRepository) -> Option<i64> {
+        repo.get_consumer_offset(&consumer_group(), DELETE_TOPIC)
+            .await
+            .unwrap()
+    }
+
+    async fn relayed(repo: &MemoryRepository, topic: &str) -> Vec<Event> {
+        let bus = MemoryEventBus::new();
+        repo.relay_outbox(&bus, 100, 0, 0).await.unwrap();
+
+        bus.events(topic)
+    }
+
+    #[tokio::test]
+    async fn deletion_purges_the_user_once() {
+        let repo = repo_with(&["alice-id"]).await;
+
+        process(
+            &repo,
+            &config(),
+            &deleted(),
+            &position(),
+            record(0, &deletion("alice-id")),
+        )
+        .await
+        .unwrap();
+
+        assert!(repo.get_public_user("alice-id").await.is_none());
+        assert_eq!(committed(&repo).await, Some(1));
+
+        // Delivered again, nothing left to purge but the offset still moves
+        process(
+            &repo,
+            &config(),
+            &deleted(),
+            &position(),
+            record(1, &deletion("alice-id")),
+        )
+        .await
+        .unwrap();
+        assert_eq!(committed(&repo).await, Some(2));
+
+        let events = relayed(&repo, "user-purged").await;
+        assert_eq!(events.len(), 1);
+
+        let purged: UserPurged = from_slice(&events[0].payload).unwrap();
+        assert_eq!(purged.user_id, "alice-id");
+        assert_eq!(purged.username, "alice");
+    }
+
+    #[tokio::test]
+    async fn registration_skips_reserved_and_held_names() {
+        let repo = repo_with(&["bob-id"]).await;
+
+        // bob gives up "bob", which stays held for them
+        let changes = HashMap::from([(
+            RequestUpdateProfileEnum::Username,
+            Some("robert".to_owned()),
+        )]);
+        let event = OutboxEvent {
+            topic: "user-updated".to_owned(),
+            key: "bob-id".to_owned(),
+            payload: Vec::new(),
+        };
+        repo.update_profile("bob-id", &changes, event)
+            .await
+            .unwrap();
+
+        let position = ConsumerOffset {
+            topic: "auth-register".to_owned(),
+            ..position()
+        };
+
+        for (offset, (id, username)) in [("mallory-id", "аdmin"), ("carol-id", "bob")]
+            .into_iter()
+            .enumerate()
+        {
+            let payload = to_vec(&json!({ "id": id, "username": username })).unwrap();
+
+            process(
+                &repo,
+                &config(),
+                &registered(),
+                &position,
+                record(offset as i64, &payload),
+            )
+            .await
+            .unwrap();
+        }
+
+        let resolved: Vec<UsernameCollisionResolved> = relayed(&repo, "user-username-resolved")
+            .await
+            .iter()
+            .map(|event| from_slice(&event.payload).unwrap())
+            .collect();
+        assert_eq!(resolved.len(), 2);
+
+        for (resolved, requested) in resolved.iter().zip(["аdmin", "bob"]) {
+            assert_eq!(resolved.requested_username, requested);
+            assert!(
+                resolved
+                    .assigned_username
+                    .starts_with(&format!("{requested}_"))
+            );
+
+            let user = repo
+                .get_private_user(&resolved.assigned_username)
+                .await
+                .unwrap();
+            assert_eq!(user.id, resolved.user_id);
+        }
+
+        // The held name still belongs to nobody but bob
+        assert!(repo.get_private_user("bob").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn deletion_before_registration_keeps_the_user_away() {
+        let repo = repo_with(&[]).await;
+
+        process(
+            &repo,
+            &config(),
+            &deleted(),
+            &position(),
+            record(0, &deletion("alice-id")),
+        )
+        .await
+        .unwrap();
+
+        assert!(repo.is_user_purged("alice-id").await.unwrap());
+        assert_eq!(committed(&repo).await, Some(1));
+
+        let payload = to_vec(&json!({ "id": "alice-id", "username": "alice" })).unwrap();
+        let position = ConsumerOffset {
+            topic: "auth-register".to_owned(),
+            ..position()
+        };
+        process(
+            &repo,
+            &config(),
+            &registered(),
+            &position,
+            record(0, &payload),
+        )
+        .await
+        .unwrap();
+
+        assert!(repo.get_public_user("alice-id").await.is_none());
+        assert!(relayed(&repo, "user-purged").await.is_empty());
+    }
 
-            if insert_user(user, &db).await.is_err() {
-                //TODO! IDK, panic I guess
+    #[tokio::test]
+    async fn registration_stores_the_normalized_username() {
+        let repo = repo_with(&["alice-id"]).await;
+        let position = ConsumerOffset {
+            topic: "auth-register".to_owned(),
+            ..position()
+        };
+
+        // Fullwidth twins of a free and of a taken name
+        for (offset, (id, username)) in [("bob-id", "ｂｏｂ"), ("mallory-id", "ａｌｉｃｅ")]
+            .into_iter()
+            .enumerate()
+        {
+            let payload = to_vec(&json!({ "id": id, "username": username })).unwrap();
+
+            process(
+                &repo,
+                &config(),
+                &registered(),
+                &position,
+                record(offset as i64, &payload),
+            )
+            .await
+            .unwrap();
+        }
+
+        let bob = repo.get_private_user("bob").await.unwrap();
+        assert_eq!(bob.id, "bob-id");
+        assert_eq!(bob.username, "bob");
+
+        let alice = repo.get_private_user("alice").await.unwrap();
+        assert_eq!(alice.id, "alice-id");
+
+        let resolved: Vec<UsernameCollisionResolved> = relayed(&repo, "user-username-resolved")
+            .await
+            .iter()
+            .map(|event| from_slice(&event.payload).unwrap())
+            .collect();
+        assert_eq!(resolved.len(), 2);
+        assert_eq!(resolved[0].assigned_username, "bob");
+        assert!(resolved[1].assigned_username.starts_with("alice_"));
+    }
+
+    #[tokio::test]
+    async fn registration_of_a_purged_user_is_skipped() {
+        let repo = repo_with(&["alice-id"]).await;
+
+        process(
+            &repo,
+            &config(),
+            &deleted(),
+            &position(),
+            record(0, &deletion("alice-id")),
+        )
+        .await
+        .unwrap();
+
+        let payload = to_vec(&json!({ "id": "alice-id", "username": "alice" })).unwrap();
+        process(
+            &repo,
+            &config(),
+            &registered(),
+            &position(),
+            record(1, &payload),
+        )
+        .await
+        .unwrap();
+
+        assert!(repo.get_public_user("alice-id").await.is_none());
+        assert_eq!(committed(&repo).await, Some(2));
+    }
+
+    #[tokio::test]
+    async fn transient_errors_are_retried() {
+        let repo = repo_with(&["alice-id"]).await;
+        repo.fail_next("find_public_user", sqlx::Error::PoolTimedOut.into());
+        repo.fail_next("delete_user", sqlx::Error::PoolTimedOut.into());
+
+        process(
+            &repo,
+            &config(),
+            &deleted(),
+            &position(),
+            record(0, &deletion("alice-id")),
+        )
+        .await
+        .unwrap();
+
+        assert!(repo.get_public_user("alice-id").await.is_none());
+        assert_eq!(committed(&repo).await, Some(1));
+        assert!(relayed(&repo, "dead-letter").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn long_retry_runs_stay_at_the_maximum_delay() {
+        let repo = repo_with(&["alice-id"]).await;
+        let config = ConsumerConfig {
+            max_retries: 40,
+            ..config()
+        };
+        for _ in 0..config.max_retries {
+            repo.fail_next("find_public_user", sqlx::Error::PoolTimedOut.into());
+        }
+
+        process(
+            &repo,
+            &config,
+            &deleted(),
+            &position(),
+            record(0, &deletion("alice-id")),
+        )
+        .await
+        .unwrap();
+
+        assert!(repo.get_public_user("alice-id").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn exhausted_retries_are_dead_lettered() {
+        let repo = repo_with(&["alice-id"]).await;
+        for _ in 0..=config().max_retries {
+            repo.fail_next("find_public_user", sqlx::Error::PoolTimedOut.into());
+        }
+
+        process(
+            &repo,
+            &config(),
+            &deleted(),
+            &position(),
+            record(0, &deletion("alice-id")),
+        )
+        .await
+        .unwrap();
+
+        assert!(repo.get_public_user("alice-id").await.is_some());
+        assert_eq!(committed(&repo).await, Some(1));
+
+        let letters = relayed(&repo, "dead-letter").await;
+        assert_eq!(letters.len(), 1);
+
+        let letter: DeadLetter = from_slice(&letters[0].payload).unwrap();
+        assert!(letter.reason.starts_with("Database error"));
+    }
+
+    #[tokio::test]
+    async fn malformed_records_are_dead_lettered() {
+        let repo = repo_with(&["alice-id"]).await;
+
+        process(
+            &repo,
+            &config(),
+            &deleted(),
+            &position(),
+            record(0, b"not json"),
+        )
+        .await
+        .unwrap();
+
+        assert!(repo.get_public_user("alice-id").await.is_some());
+        assert_eq!(committed(&repo).await, Some(1));
+
+        let letters = relayed(&repo, "dead-letter").await;
+        assert_eq!(letters.len(), 1);
+
+        let letter: DeadLetter = from_slice(&letters[0].payload).unwrap();
+        assert_eq!(letter.topic, DELETE_TOPIC);
+        assert_eq!(letter.offset, 0);
+        assert_eq!(letter.payload, "not json");
+        assert!(letter.reason.starts_with("Malformed payload"));
+    }
+
+    #[tokio::test]
+    async fn failed_dead_letter_leaves_the_offset_behind() {
+        let repo = repo_with(&[]).await;
+        repo.fail_next("dead_letter", anyhow::anyhow!("Connection lost"));
+
+        let result = process(
+            &repo,
+            &config(),
+            &deleted(),
+            &position(),
+            record(0, b"not json"),
+        )
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(committed(&repo).await, None);
+    }
+
+    #[tokio::test]
+    async fn offset_lookup_is_retried() {
+        let repo = repo_with(&[]).await;
+        repo.commit_consumer_offset(ConsumerOffset {
+            offset: 3,
+            ..position()
+        })
+        .await
+        .unwrap();
+        repo.fail_next("get_consumer_offset", sqlx::Error::PoolTimedOut.into());
+
+        let position = committed_offset(&repo, &config(), DELETE_TOPIC).await;
+        assert_eq!(position.offset, 3);
+    }
+
+    // Runs the consumer until `done` holds, failing the test if it takes too long
+    async fn consume_until<F>(
+        subscriber: Arc<dyn EventSubscriber>,
+        repo: Arc<MemoryRepository>,
+        done: impl Fn(Arc<MemoryRepository>) -> F,
+    ) where
+        F: Future<Output = bool>,
+    {
+        let consumer = tokio::spawn(consume(
+            subscriber,
+            repo.clone(),
+            Arc::new(config()),
+            DELETE_TOPIC.to_owned(),
+            deleted(),
+        ));
+
+        let finished = tokio::time::timeout(Duration::from_secs(5), async {
+            while !done(repo.clone()).await {
+                tokio::time::sleep(Duration::from_millis(5)).await;
             }
+        })
+        .await;
+
+        consumer.abort();
+        finished.expect("Consumer did not catch up");
+    }
+
+    #[tokio::test]
+    async fn stream_errors_resubscribe_from_the_committed_offset() {
+        let bus = MemoryEventBus::new();
+        for id in ["alice-id", "bob-id"] {
+            bus.publish(DELETE_TOPIC, id, &deletion(id)).await.unwrap();
         }
+
+        let repo = Arc::new(repo_with(&["alice-id", "bob-id"]).await);
+        let subscriber = Arc::new(FlakySubscriber {
+            bus,
+            subscriptions: AtomicUsize::new(0),
+        });
+
+        consume_until(subscriber.clone(), repo.clone(), |repo| async move {
+            repo.get_public_user("bob-id").await.is_none()
+        })
+        .await;
+
+        assert!(repo.get_public_user("alice-id").await.is_none());
+        assert_eq!(subscriber.subscriptions.load(Ordering::SeqCst), 2);
+        assert_eq!(committed(&repo).await, Some(2));
+        assert_eq!(relayed(&repo, "user-purged").await.len(), 2);
     }
 
-    Ok(())
+    #[tokio::test]
+    async fn records_that_could_not_be_dead_lettered_are_read_again() {
+        let bus = MemoryEventBus::new();
+        bus.publish(DELETE_TOPIC, "key", b"not json").await.unwrap();
+        bus.publish(DELETE_TOPIC, "bob-id", &deletion("bob-id"))
+            .await
+            .unwrap();
+
+        let repo = Arc::new(repo_with(&["bob-id"]).await);
+        repo.fail_next("dead_letter", anyhow::anyhow!("Connection lost"));
+
+        consume_until(Arc::new(bus), repo.clone(), |repo| async move {
+            repo.get_public_user("bob-id").await.is_none()
+        })
+        .await;
+
+        assert_eq!(committed(&repo).await, Some(2));
+        assert_eq!(relayed(&repo, "dead-letter").await.len(), 1);
+    }
 }
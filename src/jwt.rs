@@ -2,8 +2,8 @@ use std::sync::LazyLock;
 
 use axum::{
     Json, RequestPartsExt,
-    extract::FromRequestParts,
-    http::{StatusCode, request::Parts},
+    extract::{FromRequestParts, OptionalFromRequestParts},
+    http::{StatusCode, header::AUTHORIZATION, request::Parts},
     response::{IntoResponse, Response},
 };
 use axum_extra::{
@@ -35,6 +35,9 @@ impl Keys {
 pub(crate) struct Claims {
     exp: u64,
     pub user_id: String,
+    // Only needed to provision users from their token
+    #[serde(default)]
+    pub username: Option<String>,
 }
 
 impl<S> FromRequestParts<S> for Claims
@@ -57,6 +60,27 @@ where
     }
 }
 
+// Anonymous callers get None, a token that is sent has to be valid
+impl<S> OptionalFromRequestParts<S> for Claims
+where
+    S: Send + Sync,
+{
+    type Rejection = AuthError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        if !parts.headers.contains_key(AUTHORIZATION) {
+            return Ok(None);
+        }
+
+        <Claims as FromRequestParts<S>>::from_request_parts(parts, state)
+            .await
+            .map(Some)
+    }
+}
+
 //This should be a common crate for all services, dead code is allowed to preserve the common structure
 #[allow(dead_code)]
 #[derive(Debug)]
@@ -1,6 +1,13 @@
-pub(crate) mod api_utils;
+pub mod api_utils;
 pub mod app;
+pub mod event_bus;
+pub(crate) mod events;
 pub(crate) mod fluvio_consumer;
 pub(crate) mod jwt;
+pub(crate) mod outbox_relay;
+pub(crate) mod policy;
+pub(crate) mod provisioning;
+pub mod repository;
 pub(crate) mod request;
+pub(crate) mod social_path;
 pub(crate) mod sql_utils;
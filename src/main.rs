@@ -2,5 +2,18 @@ use user_service::app;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    app::run().await
+    let mut args = std::env::args().skip(1);
+
+    match args.next().as_deref() {
+        Some("migrate") => app::run_migrations().await,
+        Some("replay") => {
+            let topic = args
+                .next()
+                .expect("Usage: user-service replay <topic> [offset]");
+            let offset = args.next().map(|e| e.parse()).transpose()?.unwrap_or(0);
+
+            app::replay_topic(&topic, offset).await
+        }
+        _ => app::run().await,
+    }
 }
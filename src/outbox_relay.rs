@@ -0,0 +1,44 @@
+use std::{sync::Arc, time::Duration};
+
+use dotenvy::var;
+
+use crate::{event_bus::EventPublisher, repository::Repository};
+
+pub async fn run(
+    publisher: Arc<dyn EventPublisher>,
+    repo: Arc<dyn Repository>,
+) -> anyhow::Result<()> {
+    let poll_interval: u64 = var("OUTBOX_POLL_INTERVAL_MS")
+        .unwrap_or("500".to_owned())
+        .parse()
+        .expect("OUTBOX_POLL_INTERVAL_MS must be a number");
+
+    let batch_size: i64 = var("OUTBOX_BATCH_SIZE")
+        .unwrap_or("100".to_owned())
+        .parse()
+        .expect("OUTBOX_BATCH_SIZE must be a number");
+
+    let lease: i64 = var("OUTBOX_LEASE_SECS")
+        .unwrap_or("60".to_owned())
+        .parse()
+        .expect("OUTBOX_LEASE_SECS must be a number");
+
+    let max_backoff: i64 = var("OUTBOX_MAX_BACKOFF_SECS")
+        .unwrap_or("300".to_owned())
+        .parse()
+        .expect("OUTBOX_MAX_BACKOFF_SECS must be a number");
+
+    loop {
+        match repo
+            .relay_outbox(publisher.as_ref(), batch_size, lease, max_backoff)
+            .await
+        {
+            // A full batch means there is probably more waiting, go again right away
+            Ok(relayed) if relayed as i64 == batch_size => continue,
+            Ok(_) => {}
+            Err(e) => tracing::error!("Outbox relay failed: {e:?}"),
+        }
+
+        tokio::time::sleep(Duration::from_millis(poll_interval)).await;
+    }
+}
@@ -0,0 +1,2 @@
+pub(crate) mod profile;
+pub(crate) mod username;
@@ -0,0 +1,69 @@
+use url::Url;
+
+use crate::{
+    api_utils::{
+        responses::{self, ApiResponse, ApiResponseMessage},
+        structs::RequestUpdateProfileEnum,
+    },
+    policy::username::validate_username,
+};
+
+pub const DISPLAY_NAME_MAX_CHARS: usize = 32;
+pub const BIO_MAX_CHARS: usize = 300;
+pub const URL_MAX_CHARS: usize = 2048;
+
+/// Checks a single profile change and returns the value to store, `None` clears the field
+pub fn validate_profile_field(
+    part: &RequestUpdateProfileEnum,
+    value: &str,
+    reserved_usernames: &[String],
+) -> Result<Option<String>, ApiResponse<ApiResponseMessage>> {
+    use RequestUpdateProfileEnum::*;
+
+    let value = value.trim();
+
+    match part {
+        Username => validate_username(value, reserved_usernames).map(Some),
+        DisplayName => optional(value, |v| {
+            let len = v.chars().count();
+            (len <= DISPLAY_NAME_MAX_CHARS && !v.chars().any(char::is_control))
+                .then(|| v.to_owned())
+                .ok_or(responses::INVALID_DISPLAY_NAME)
+        }),
+        Bio => optional(value, |v| {
+            let len = v.chars().count();
+            (len <= BIO_MAX_CHARS && !v.chars().any(|c| c.is_control() && c != '\n'))
+                .then(|| v.to_owned())
+                .ok_or(responses::INVALID_BIO)
+        }),
+        Avatar => optional(value, |v| https_url(v).ok_or(responses::INVALID_AVATAR)),
+        Banner => optional(value, |v| https_url(v).ok_or(responses::INVALID_BANNER)),
+        Country => optional(value, |v| {
+            // ISO 3166-1 alpha-2
+            (v.len() == 2 && v.chars().all(|c| c.is_ascii_alphabetic()))
+                .then(|| v.to_ascii_uppercase())
+                .ok_or(responses::INVALID_COUNTRY)
+        }),
+    }
+}
+
+fn optional(
+    value: &str,
+    validate: impl FnOnce(&str) -> Result<String, ApiResponse<ApiResponseMessage>>,
+) -> Result<Option<String>, ApiResponse<ApiResponseMessage>> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+
+    validate(value).map(Some)
+}
+
+fn https_url(value: &str) -> Option<String> {
+    if value.len() > URL_MAX_CHARS {
+        return None;
+    }
+
+    let url = Url::parse(value).ok()?;
+
+    (url.scheme() == "https" && url.host_str().is_some()).then(|| url.to_string())
+}
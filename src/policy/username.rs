@@ -0,0 +1,109 @@
+use sha2::{Digest, Sha256};
+use unicode_normalization::UnicodeNormalization;
+use unicode_security::skeleton;
+
+use crate::api_utils::responses::{self, ApiResponse, ApiResponseMessage};
+
+pub const USERNAME_MIN_CHARS: usize = 3;
+pub const USERNAME_MAX_CHARS: usize = 32;
+
+/// Used when RESERVED_USERNAMES is not set
+pub const DEFAULT_RESERVED_USERNAMES: &str =
+    "admin,administrator,root,system,support,staff,moderator,mod,official,kiwi,me,null,undefined";
+
+/// NFKC form of the username, what gets stored and compared
+pub fn normalize_username(raw: &str) -> String {
+    raw.trim().nfkc().collect()
+}
+
+/// UTS #39 skeleton of the username, two names with the same skeleton look alike. Case is
+/// folded on both sides of the mapping since some prototypes are uppercase, '0' maps to 'O'
+pub fn username_skeleton(username: &str) -> String {
+    skeleton(&username.to_lowercase())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// Skeleton without separators, catches names that only differ by a '_', '-' or '.'
+pub fn loose_username_skeleton(username: &str) -> String {
+    username_skeleton(username)
+        .chars()
+        .filter(|c| !matches!(c, '_' | '-' | '.'))
+        .collect()
+}
+
+/// Alternatives to `username` when it is already taken, always the same for a given user id.
+/// Suffixes come from the id hash so two users colliding on the same name diverge right away
+pub fn collision_candidates(username: &str, user_id: &str) -> impl Iterator<Item = String> {
+    let tag: String = Sha256::digest(user_id.as_bytes())
+        .iter()
+        .take(3)
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+
+    (0..).map(move |n| {
+        let suffix = match n {
+            0 => format!("_{tag}"),
+            n => format!("_{tag}{n}"),
+        };
+
+        let base: String = username
+            .chars()
+            .take(USERNAME_MAX_CHARS.saturating_sub(suffix.chars().count()))
+            .collect();
+
+        format!("{base}{suffix}")
+    })
+}
+
+/// Parses a comma separated list into lowercase reserved names
+pub fn parse_reserved_usernames(list: &str) -> Vec<String> {
+    list.split(",")
+        .map(|e| normalize_username(e).to_lowercase())
+        .filter(|e| !e.is_empty())
+        .collect()
+}
+
+/// Whether a normalized `username` is one of `reserved` or looks like one, like "аdmin"
+/// with a cyrillic 'а'
+pub fn is_reserved(username: &str, reserved: &[String]) -> bool {
+    let lowered = username.to_lowercase();
+    let lookalike = username_skeleton(username);
+
+    reserved
+        .iter()
+        .any(|e| *e == lowered || username_skeleton(e) == lookalike)
+}
+
+/// Normalizes `raw` and checks it against the length, charset and reserved rules.
+/// Uniqueness is up to the caller since it needs the repository
+pub fn validate_username(
+    raw: &str,
+    reserved: &[String],
+) -> Result<String, ApiResponse<ApiResponseMessage>> {
+    let username = normalize_username(raw);
+
+    let len = username.chars().count();
+    if !(USERNAME_MIN_CHARS..=USERNAME_MAX_CHARS).contains(&len) {
+        return Err(responses::INVALID_USERNAME);
+    }
+
+    // Letters and digits of any script, separators only between them
+    let is_separator = |c: char| matches!(c, '_' | '-' | '.');
+    let starts_and_ends_alphanumeric =
+        username.starts_with(char::is_alphanumeric) && username.ends_with(char::is_alphanumeric);
+
+    if !starts_and_ends_alphanumeric
+        || !username
+            .chars()
+            .all(|c| c.is_alphanumeric() || is_separator(c))
+    {
+        return Err(responses::INVALID_USERNAME);
+    }
+
+    if is_reserved(&username, reserved) {
+        return Err(responses::USERNAME_RESERVED);
+    }
+
+    Ok(username)
+}
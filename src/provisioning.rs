@@ -0,0 +1,179 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{FromRequestParts, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{Duration, Utc};
+use serde_json::to_vec;
+
+use crate::{
+    api_utils::structs::{OutboxEvent, PrivateUser},
+    app::AppState,
+    events::UsernameCollisionResolved,
+    jwt::Claims,
+    policy::username::{collision_candidates, is_reserved, normalize_username},
+    repository::Repository,
+};
+
+/// What a new user's name is checked against besides the existing users
+pub struct UsernameRules<'a> {
+    pub reserved: &'a [String],
+    /// How long a released name stays held for its previous owner
+    pub hold_secs: i64,
+}
+
+/// Builds the row for a new user, renamed when `username` is taken, reserved or on hold here
+/// along with the event telling auth about it. The name is stored normalized like a rename,
+/// auth hears about that too when it changes the name
+pub async fn prepare_user(
+    repo: &dyn Repository,
+    user_id: &str,
+    username: &str,
+    rules: &UsernameRules<'_>,
+    resolved_topic: &str,
+) -> anyhow::Result<(PrivateUser, Option<OutboxEvent>)> {
+    let normalized = normalize_username(username);
+    let assigned = available_username(repo, &normalized, user_id, rules).await;
+
+    let event = if assigned != username {
+        let resolved = UsernameCollisionResolved {
+            user_id: user_id.to_owned(),
+            requested_username: username.to_owned(),
+            assigned_username: assigned.clone(),
+        };
+
+        Some(OutboxEvent {
+            topic: resolved_topic.to_owned(),
+            key: user_id.to_owned(),
+            payload: to_vec(&resolved)?,
+        })
+    } else {
+        None
+    };
+
+    let user = PrivateUser {
+        id: user_id.to_owned(),
+        username: assigned,
+        created_at: None,
+    };
+
+    Ok((user, event))
+}
+
+// The requested name, or the first collision candidate nobody holds or looks like
+async fn available_username(
+    repo: &dyn Repository,
+    username: &str,
+    user_id: &str,
+    rules: &UsernameRules<'_>,
+) -> String {
+    if is_free(repo, username, user_id, rules).await {
+        return username.to_owned();
+    }
+
+    for candidate in collision_candidates(username, user_id) {
+        if is_free(repo, &candidate, user_id, rules).await {
+            return candidate;
+        }
+    }
+
+    unreachable!("collision_candidates never ends")
+}
+
+// Same rules a rename goes through, except the format which auth already checked
+async fn is_free(
+    repo: &dyn Repository,
+    username: &str,
+    user_id: &str,
+    rules: &UsernameRules<'_>,
+) -> bool {
+    if is_reserved(username, rules.reserved) {
+        return false;
+    }
+
+    let since = Utc::now() - Duration::seconds(rules.hold_secs);
+
+    repo.get_private_user(username).await.is_none()
+        && repo.get_confusable_user(username).await.is_none()
+        && repo
+            .get_previous_username_owner(username, since)
+            .await
+            .is_none_or(|user| user.id == user_id)
+}
+
+/// Creates the caller's row from its token when the registration event has not arrived yet.
+/// Costs a user lookup per authenticated request, so it only runs when enabled
+pub async fn provision_from_claims(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !state.provision_users_from_claims {
+        return next.run(request).await;
+    }
+
+    let (mut parts, body) = request.into_parts();
+
+    // Invalid tokens are left for the handler to reject
+    if let Ok(claims) = Claims::from_request_parts(&mut parts, &state).await {
+        provision(&state, claims).await;
+    }
+
+    next.run(Request::from_parts(parts, body)).await
+}
+
+async fn provision(state: &AppState, claims: Claims) {
+    // Tokens issued before the username claim existed can not provision anything
+    let Some(username) = claims.username else {
+        return;
+    };
+
+    if state.repo.get_public_user(&claims.user_id).await.is_some() {
+        return;
+    }
+
+    // Tokens outlive the account, a purged user must not come back through one
+    match state.repo.is_user_purged(&claims.user_id).await {
+        Ok(false) => {}
+        Ok(true) => return,
+        Err(e) => {
+            tracing::warn!("Failed to provision user {}: {e:?}", claims.user_id);
+            return;
+        }
+    }
+
+    let rules = UsernameRules {
+        reserved: &state.reserved_usernames,
+        hold_secs: state.username_hold_secs,
+    };
+
+    let (user, event) = match prepare_user(
+        state.repo.as_ref(),
+        &claims.user_id,
+        &username,
+        &rules,
+        &state.username_resolved_topic,
+    )
+    .await
+    {
+        Ok(e) => e,
+        Err(e) => {
+            tracing::warn!("Failed to provision user {}: {e:?}", claims.user_id);
+            return;
+        }
+    };
+
+    let Err(e) = state.repo.insert_user(user, event, None).await else {
+        tracing::info!("Provisioned user {} from token claims", claims.user_id);
+        return;
+    };
+
+    // Losing a race against the consumer is fine, the user exists either way
+    if let Ok(Some(_)) = state.repo.find_public_user(&claims.user_id).await {
+        return;
+    }
+
+    tracing::warn!("Failed to provision user {}: {e:?}", claims.user_id);
+}
@@ -0,0 +1,1147 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard},
+};
+
+use anyhow::{anyhow, bail};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::{
+    api_utils::structs::{
+        ConsumerOffset, FriendRequestPolicy, FriendRequestState, OutboxEvent, PrivateBlocked,
+        PrivateFriendRequest, PrivateFriendship, PrivateOutboxEvent, PrivateProfile, PrivateUser,
+        PublicBlocked, PublicFriendRequestReceived, PublicFriendRequestSent,
+        PublicFriendSuggestion, PublicFriendship, PublicUser, RequestUpdateProfileEnum,
+        UserSettings, Visibility,
+    },
+    event_bus::EventPublisher,
+    policy::username::{normalize_username, username_skeleton},
+    repository::{
+        ConsumerOffsetRepository, OutboxRepository, SocialGraphRepository, UserRepository,
+    },
+};
+
+// Optional columns of the users table
+#[derive(Default)]
+struct Profile {
+    display_name: Option<String>,
+    bio: Option<String>,
+    avatar_url: Option<String>,
+    banner_url: Option<String>,
+    country: Option<String>,
+}
+
+struct UsernameChange {
+    user_id: String,
+    username: String,
+    released_at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+struct Tables {
+    users: Vec<PrivateUser>,
+    // Keyed by user id
+    profiles: HashMap<String, Profile>,
+    // Keyed by user id
+    settings: HashMap<String, UserSettings>,
+    username_history: Vec<UsernameChange>,
+    friendships: Vec<PrivateFriendship>,
+    friend_requests: Vec<PrivateFriendRequest>,
+    blocks: Vec<PrivateBlocked>,
+    outbox: Vec<PrivateOutboxEvent>,
+    // Outbox ids already handed to the publisher
+    published: Vec<i64>,
+    // Keyed by (consumer, topic)
+    consumer_offsets: HashMap<(String, String), i64>,
+    // Ids of deleted users
+    purged_users: Vec<String>,
+}
+
+impl Tables {
+    fn username(&self, id: &str) -> Option<String> {
+        self.users
+            .iter()
+            .find(|u| u.id == id)
+            .map(|u| u.username.clone())
+    }
+
+    fn commit_offset(&mut self, offset: ConsumerOffset) {
+        self.consumer_offsets
+            .insert((offset.consumer, offset.topic), offset.offset);
+    }
+
+    // Same as `ON CONFLICT (user_id) DO NOTHING`
+    fn tombstone(&mut self, id: &str) {
+        if !self.purged_users.iter().any(|p| p == id) {
+            self.purged_users.push(id.to_owned());
+        }
+    }
+
+    // A block in either direction
+    fn blocked_between(&self, a_user_id: &str, b_user_id: &str) -> bool {
+        self.blocks.iter().any(|b| {
+            (b.from_user_id == a_user_id && b.to_user_id == b_user_id)
+                || (b.from_user_id == b_user_id && b.to_user_id == a_user_id)
+        })
+    }
+
+    fn profile_mut(&mut self, id: &str) -> &mut Profile {
+        self.profiles.entry(id.to_owned()).or_default()
+    }
+
+    fn clone_request(request: &PrivateFriendRequest) -> PrivateFriendRequest {
+        PrivateFriendRequest {
+            from_user_id: request.from_user_id.clone(),
+            to_user_id: request.to_user_id.clone(),
+            state: request.state.clone(),
+            created_at: request.created_at,
+            responded_at: request.responded_at,
+        }
+    }
+
+    // Latest request of every pair, in the order the sql listing returns them
+    fn latest_requests(
+        &self,
+        filter: impl Fn(&PrivateFriendRequest) -> bool,
+    ) -> Vec<&PrivateFriendRequest> {
+        let mut latest: Vec<&PrivateFriendRequest> = Vec::new();
+
+        for request in self.friend_requests.iter().filter(|r| filter(r)) {
+            latest.retain(|l| {
+                !(l.from_user_id == request.from_user_id && l.to_user_id == request.to_user_id)
+            });
+            latest.push(request);
+        }
+
+        let cancelled = FriendRequestState::Cancelled.to_string();
+        latest.retain(|r| r.state != cancelled);
+
+        latest
+    }
+
+    fn has_pending_request(&self, from_user_id: &str, to_user_id: &str) -> bool {
+        let pending = FriendRequestState::Pending.to_string();
+
+        self.friend_requests
+            .iter()
+            .rev()
+            .find(|r| r.from_user_id == from_user_id && r.to_user_id == to_user_id)
+            .is_some_and(|r| r.state == pending)
+    }
+
+    fn answer_request(
+        &mut self,
+        from_user_id: &str,
+        to_user_id: &str,
+        state: FriendRequestState,
+    ) -> anyhow::Result<()> {
+        let pending = FriendRequestState::Pending.to_string();
+
+        let request = self
+            .friend_requests
+            .iter_mut()
+            .rev()
+            .find(|r| r.from_user_id == from_user_id && r.to_user_id == to_user_id)
+            .filter(|r| r.state == pending)
+            .ok_or_else(|| anyhow!("No pending request from {from_user_id} to {to_user_id}"))?;
+
+        request.state = state.to_string();
+        request.responded_at = Some(Utc::now());
+
+        Ok(())
+    }
+
+    fn push_outbox(&mut self, event: OutboxEvent) {
+        let id = self.outbox.len() as i64 + 1;
+        self.outbox.push(PrivateOutboxEvent {
+            id,
+            topic: event.topic,
+            key: event.key,
+            payload: event.payload,
+            attempts: 0,
+        });
+    }
+}
+
+/// Mirrors the postgres schema and constraints in memory, meant for tests and local runs
+#[derive(Default)]
+pub struct MemoryRepository {
+    tables: Mutex<Tables>,
+    // Queued by `fail_next`, keyed by method name
+    failures: Mutex<HashMap<&'static str, Vec<anyhow::Error>>>,
+}
+
+impl MemoryRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes the next call of `method` return `error` without touching anything, only the
+    /// methods the consumer calls can fail. Several errors for the same method come in order
+    pub fn fail_next(&self, method: &'static str, error: anyhow::Error) {
+        self.failures
+            .lock()
+            .expect("Repository lock poisoned")
+            .entry(method)
+            .or_default()
+            .push(error);
+    }
+
+    fn tables(&self) -> MutexGuard<'_, Tables> {
+        self.tables.lock().expect("Repository lock poisoned")
+    }
+
+    fn injected_failure(&self, method: &'static str) -> anyhow::Result<()> {
+        let mut failures = self.failures.lock().expect("Repository lock poisoned");
+
+        match failures.get_mut(method) {
+            Some(errors) if !errors.is_empty() => Err(errors.remove(0)),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Same semantics as the `lower(username)` unique index
+fn same_username(a: &str, b: &str) -> bool {
+    a.to_lowercase() == b.to_lowercase()
+}
+
+// Both unique indexes on users, `lower(username)` and `username_skeleton`
+fn username_conflicts(a: &str, b: &str) -> bool {
+    same_username(a, b) || username_skeleton(a) == username_skeleton(b)
+}
+
+// Same semantics as `LIMIT $limit OFFSET $offset`, negative values are a query error
+fn page<T>(items: Vec<T>, offset: i64, limit: i64) -> Option<Vec<T>> {
+    if offset < 0 || limit < 0 {
+        return None;
+    }
+
+    Some(
+        items
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect(),
+    )
+}
+
+#[async_trait]
+impl UserRepository for MemoryRepository {
+    async fn get_public_user(&self, id: &str) -> Option<PublicUser> {
+        self.tables()
+            .users
+            .iter()
+            .find(|u| u.id == id)
+            .map(|u| PublicUser {
+                username: u.username.clone(),
+                created_at: u.created_at,
+            })
+    }
+
+    async fn find_public_user(&self, id: &str) -> anyhow::Result<Option<PublicUser>> {
+        self.injected_failure("find_public_user")?;
+
+        Ok(self.get_public_user(id).await)
+    }
+
+    async fn is_user_purged(&self, id: &str) -> anyhow::Result<bool> {
+        Ok(self.tables().purged_users.iter().any(|p| p == id))
+    }
+
+    async fn get_private_user(&self, username: &str) -> Option<PrivateUser> {
+        let username = normalize_username(username);
+
+        self.tables()
+            .users
+            .iter()
+            .find(|u| same_username(&u.username, &username))
+            .map(|u| PrivateUser {
+                id: u.id.clone(),
+                username: u.username.clone(),
+                created_at: u.created_at,
+            })
+    }
+
+    async fn get_confusable_user(&self, username: &str) -> Option<PrivateUser> {
+        let skeleton = username_skeleton(&normalize_username(username));
+
+        self.tables()
+            .users
+            .iter()
+            .find(|u| username_skeleton(&u.username) == skeleton)
+            .map(|u| PrivateUser {
+                id: u.id.clone(),
+                username: u.username.clone(),
+                created_at: u.created_at,
+            })
+    }
+
+    async fn get_private_profile(&self, username: &str) -> Option<PrivateProfile> {
+        let username = normalize_username(username);
+        let tables = self.tables();
+
+        let user = tables
+            .users
+            .iter()
+            .find(|u| same_username(&u.username, &username))?;
+        let profile = tables.profiles.get(&user.id);
+
+        Some(PrivateProfile {
+            id: user.id.clone(),
+            username: user.username.clone(),
+            display_name: profile.and_then(|p| p.display_name.clone()),
+            bio: profile.and_then(|p| p.bio.clone()),
+            avatar_url: profile.and_then(|p| p.avatar_url.clone()),
+            banner_url: profile.and_then(|p| p.banner_url.clone()),
+            country: profile.and_then(|p| p.country.clone()),
+            created_at: user.created_at,
+        })
+    }
+
+    async fn get_last_username_change(&self, user_id: &str) -> Option<DateTime<Utc>> {
+        self.tables()
+            .username_history
+            .iter()
+            .filter(|h| h.user_id == user_id)
+            .map(|h| h.released_at)
+            .max()
+    }
+
+    async fn get_settings(&self, user_id: &str) -> Option<UserSettings> {
+        self.tables().settings.get(user_id).copied()
+    }
+
+    async fn update_settings(&self, user_id: &str, settings: UserSettings) -> anyhow::Result<()> {
+        let mut tables = self.tables();
+
+        if !tables.users.iter().any(|u| u.id == user_id) {
+            bail!("User {user_id} does not exist");
+        }
+
+        tables.settings.insert(user_id.to_owned(), settings);
+
+        Ok(())
+    }
+
+    async fn get_previous_username_owner(
+        &self,
+        username: &str,
+        since: DateTime<Utc>,
+    ) -> Option<PrivateUser> {
+        let username = normalize_username(username);
+        let tables = self.tables();
+
+        let change = tables
+            .username_history
+            .iter()
+            .filter(|h| same_username(&h.username, &username) && h.released_at > since)
+            .max_by_key(|h| h.released_at)?;
+
+        tables
+            .users
+            .iter()
+            .find(|u| u.id == change.user_id)
+            .map(|u| PrivateUser {
+                id: u.id.clone(),
+                username: u.username.clone(),
+                created_at: u.created_at,
+            })
+    }
+
+    async fn insert_user(
+        &self,
+        user: PrivateUser,
+        event: Option<OutboxEvent>,
+        offset: Option<ConsumerOffset>,
+    ) -> anyhow::Result<()> {
+        self.injected_failure("insert_user")?;
+
+        let mut tables = self.tables();
+
+        if tables
+            .users
+            .iter()
+            .any(|u| u.id == user.id || username_conflicts(&u.username, &user.username))
+        {
+            bail!("User {} already exists", user.id);
+        }
+
+        tables.users.push(PrivateUser {
+            created_at: Some(Utc::now()),
+            ..user
+        });
+
+        if let Some(event) = event {
+            tables.push_outbox(event);
+        }
+
+        if let Some(offset) = offset {
+            tables.commit_offset(offset);
+        }
+
+        Ok(())
+    }
+
+    async fn delete_user(
+        &self,
+        id: &str,
+        event: OutboxEvent,
+        offset: Option<ConsumerOffset>,
+    ) -> anyhow::Result<()> {
+        self.injected_failure("delete_user")?;
+
+        let mut tables = self.tables();
+
+        // Same rows the ON DELETE CASCADE constraints take with the user
+        tables.users.retain(|u| u.id != id);
+        tables.profiles.remove(id);
+        tables.settings.remove(id);
+        tables.username_history.retain(|h| h.user_id != id);
+        tables
+            .friendships
+            .retain(|f| f.from_user_id != id && f.to_user_id != id);
+        tables
+            .friend_requests
+            .retain(|r| r.from_user_id != id && r.to_user_id != id);
+        tables
+            .blocks
+            .retain(|b| b.from_user_id != id && b.to_user_id != id);
+
+        tables.tombstone(id);
+        tables.push_outbox(event);
+
+        if let Some(offset) = offset {
+            tables.commit_offset(offset);
+        }
+
+        Ok(())
+    }
+
+    async fn insert_purged_user(&self, id: &str, offset: ConsumerOffset) -> anyhow::Result<()> {
+        self.injected_failure("insert_purged_user")?;
+
+        let mut tables = self.tables();
+
+        tables.tombstone(id);
+        tables.commit_offset(offset);
+
+        Ok(())
+    }
+
+    async fn update_profile(
+        &self,
+        id: &str,
+        changes: &HashMap<RequestUpdateProfileEnum, Option<String>>,
+        event: OutboxEvent,
+    ) -> anyhow::Result<()> {
+        let mut tables = self.tables();
+
+        if !tables.users.iter().any(|u| u.id == id) {
+            bail!("User {id} does not exist");
+        }
+
+        // Validate everything first so a failure leaves the user untouched
+        if let Some(username) = changes.get(&RequestUpdateProfileEnum::Username) {
+            let Some(username) = username else {
+                bail!("Username can not be null");
+            };
+
+            if tables
+                .users
+                .iter()
+                .any(|u| u.id != id && username_conflicts(&u.username, username))
+            {
+                bail!("Username {username} already taken");
+            }
+        }
+
+        for (part, value) in changes.iter() {
+            let value = value.clone();
+
+            match part {
+                RequestUpdateProfileEnum::Username => {
+                    let Some(user) = tables.users.iter_mut().find(|u| u.id == id) else {
+                        continue;
+                    };
+
+                    let username = value.unwrap_or_default();
+                    if user.username == username {
+                        continue;
+                    }
+
+                    let released = std::mem::replace(&mut user.username, username);
+                    tables.username_history.push(UsernameChange {
+                        user_id: id.to_owned(),
+                        username: released,
+                        released_at: Utc::now(),
+                    });
+                }
+                RequestUpdateProfileEnum::DisplayName => {
+                    tables.profile_mut(id).display_name = value
+                }
+                RequestUpdateProfileEnum::Bio => tables.profile_mut(id).bio = value,
+                RequestUpdateProfileEnum::Avatar => tables.profile_mut(id).avatar_url = value,
+                RequestUpdateProfileEnum::Banner => tables.profile_mut(id).banner_url = value,
+                RequestUpdateProfileEnum::Country => tables.profile_mut(id).country = value,
+            }
+        }
+
+        tables.push_outbox(event);
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SocialGraphRepository for MemoryRepository {
+    async fn get_private_friendship(
+        &self,
+        from_user_id: &str,
+        to_user_id: &str,
+    ) -> Option<PrivateFriendship> {
+        self.tables()
+            .friendships
+            .iter()
+            .find(|f| f.from_user_id == from_user_id && f.to_user_id == to_user_id)
+            .map(|f| PrivateFriendship {
+                from_user_id: f.from_user_id.clone(),
+                to_user_id: f.to_user_id.clone(),
+                created_at: f.created_at,
+            })
+    }
+
+    async fn get_public_friendships(
+        &self,
+        from_user_id: &str,
+        from: i64,
+        to: i64,
+    ) -> Option<Vec<PublicFriendship>> {
+        if from > to {
+            return None;
+        }
+
+        let tables = self.tables();
+
+        let friends = tables
+            .friendships
+            .iter()
+            .filter(|f| f.to_user_id == from_user_id)
+            .filter_map(|f| {
+                Some(PublicFriendship {
+                    username: tables.username(&f.from_user_id)?,
+                    created_at: f.created_at,
+                })
+            })
+            .collect();
+
+        page(friends, from, (to - from).max(1))
+    }
+
+    async fn get_public_mutual_friendships(
+        &self,
+        user_id: &str,
+        other_user_id: &str,
+        from: i64,
+        to: i64,
+    ) -> Option<Vec<PublicFriendship>> {
+        if from > to {
+            return None;
+        }
+
+        let tables = self.tables();
+
+        let friends = tables
+            .friendships
+            .iter()
+            .filter(|f| f.to_user_id == user_id)
+            .filter(|f| {
+                tables
+                    .friendships
+                    .iter()
+                    .any(|g| g.from_user_id == f.from_user_id && g.to_user_id == other_user_id)
+            })
+            .filter_map(|f| {
+                Some(PublicFriendship {
+                    username: tables.username(&f.from_user_id)?,
+                    created_at: f.created_at,
+                })
+            })
+            .collect();
+
+        page(friends, from, (to - from).max(1))
+    }
+
+    async fn get_public_friend_suggestions(
+        &self,
+        user_id: &str,
+        from: i64,
+        to: i64,
+    ) -> Option<Vec<PublicFriendSuggestion>> {
+        if from > to {
+            return None;
+        }
+
+        let tables = self.tables();
+        let pending = FriendRequestState::Pending.to_string();
+
+        let is_friend = |id: &str| {
+            tables
+                .friendships
+                .iter()
+                .any(|f| f.from_user_id == user_id && f.to_user_id == id)
+        };
+        let between = |from_id: &str, to_id: &str, a: &str, b: &str| {
+            (from_id == a && to_id == b) || (from_id == b && to_id == a)
+        };
+
+        let mut mutual_friends: HashMap<&str, i64> = HashMap::new();
+
+        for mine in tables
+            .friendships
+            .iter()
+            .filter(|f| f.to_user_id == user_id)
+        {
+            for theirs in tables
+                .friendships
+                .iter()
+                .filter(|f| f.from_user_id == mine.from_user_id)
+            {
+                *mutual_friends
+                    .entry(theirs.to_user_id.as_str())
+                    .or_default() += 1;
+            }
+        }
+
+        let mut suggestions: Vec<PublicFriendSuggestion> = mutual_friends
+            .into_iter()
+            .filter(|(id, _)| *id != user_id && !is_friend(id))
+            .filter(|(id, _)| {
+                !tables
+                    .blocks
+                    .iter()
+                    .any(|b| between(&b.from_user_id, &b.to_user_id, user_id, id))
+            })
+            .filter(|(id, _)| {
+                !tables.friend_requests.iter().any(|r| {
+                    r.state == pending && between(&r.from_user_id, &r.to_user_id, user_id, id)
+                })
+            })
+            .filter(|(id, _)| {
+                tables
+                    .settings
+                    .get(*id)
+                    .is_none_or(|s| s.friend_requests != FriendRequestPolicy::Nobody)
+            })
+            .filter_map(|(id, mutual_friends)| {
+                Some(PublicFriendSuggestion {
+                    username: tables.username(id)?,
+                    mutual_friends,
+                })
+            })
+            .collect();
+
+        suggestions.sort_by(|a, b| {
+            b.mutual_friends
+                .cmp(&a.mutual_friends)
+                .then_with(|| a.username.cmp(&b.username))
+        });
+
+        page(suggestions, from, (to - from).max(1))
+    }
+
+    async fn get_traversable_friendships(
+        &self,
+        viewer_id: &str,
+        user_ids: &[String],
+        limit: i64,
+    ) -> Option<Vec<PrivateFriendship>> {
+        if limit < 0 {
+            return None;
+        }
+
+        let tables = self.tables();
+
+        let traversable = |id: &str| {
+            let visible = tables
+                .settings
+                .get(id)
+                .is_none_or(|s| s.friends_visibility == Visibility::Public)
+                || tables
+                    .friendships
+                    .iter()
+                    .any(|f| f.from_user_id == viewer_id && f.to_user_id == id);
+
+            let blocked = tables.blocks.iter().any(|b| {
+                (b.from_user_id == viewer_id && b.to_user_id == id)
+                    || (b.from_user_id == id && b.to_user_id == viewer_id)
+            });
+
+            id == viewer_id || (visible && !blocked)
+        };
+
+        Some(
+            tables
+                .friendships
+                .iter()
+                .filter(|f| user_ids.contains(&f.to_user_id) && traversable(&f.from_user_id))
+                .take(limit as usize)
+                .map(|f| PrivateFriendship {
+                    from_user_id: f.from_user_id.clone(),
+                    to_user_id: f.to_user_id.clone(),
+                    created_at: f.created_at,
+                })
+                .collect(),
+        )
+    }
+
+    async fn has_mutual_friend(&self, user_id: &str, other_user_id: &str) -> bool {
+        let tables = self.tables();
+
+        tables
+            .friendships
+            .iter()
+            .filter(|f| f.from_user_id == user_id)
+            .any(|f| {
+                tables
+                    .friendships
+                    .iter()
+                    .any(|g| g.from_user_id == f.to_user_id && g.to_user_id == other_user_id)
+            })
+    }
+
+    async fn get_private_friend_request(
+        &self,
+        from_user_id: &str,
+        to_user_id: &str,
+    ) -> Option<PrivateFriendRequest> {
+        self.tables()
+            .friend_requests
+            .iter()
+            .rev()
+            .find(|r| r.from_user_id == from_user_id && r.to_user_id == to_user_id)
+            .map(Tables::clone_request)
+    }
+
+    async fn get_latest_friend_request(
+        &self,
+        a_user_id: &str,
+        b_user_id: &str,
+    ) -> Option<PrivateFriendRequest> {
+        self.tables()
+            .friend_requests
+            .iter()
+            .rev()
+            .find(|r| {
+                (r.from_user_id == a_user_id && r.to_user_id == b_user_id)
+                    || (r.from_user_id == b_user_id && r.to_user_id == a_user_id)
+            })
+            .map(Tables::clone_request)
+    }
+
+    async fn get_public_friend_requests_received(
+        &self,
+        to_user_id: &str,
+        from: i64,
+        to: i64,
+    ) -> Option<Vec<PublicFriendRequestReceived>> {
+        if from > to {
+            return None;
+        }
+
+        let tables = self.tables();
+
+        let requests = tables
+            .latest_requests(|r| r.to_user_id == to_user_id)
+            .into_iter()
+            .filter(|r| !tables.blocked_between(&r.from_user_id, &r.to_user_id))
+            .filter_map(|r| {
+                Some(PublicFriendRequestReceived {
+                    from_user_username: tables.username(&r.from_user_id)?,
+                    state: r.state.clone(),
+                    created_at: r.created_at,
+                })
+            })
+            .collect();
+
+        page(requests, from, (to - from).max(1))
+    }
+
+    async fn get_public_friend_requests_sent(
+        &self,
+        from_user_id: &str,
+        from: i64,
+        to: i64,
+    ) -> Option<Vec<PublicFriendRequestSent>> {
+        if from > to {
+            return None;
+        }
+
+        let tables = self.tables();
+
+        let requests = tables
+            .latest_requests(|r| r.from_user_id == from_user_id)
+            .into_iter()
+            .filter(|r| !tables.blocked_between(&r.from_user_id, &r.to_user_id))
+            .filter_map(|r| {
+                Some(PublicFriendRequestSent {
+                    to_user_username: tables.username(&r.to_user_id)?,
+                    state: r.state.clone(),
+                    created_at: r.created_at,
+                })
+            })
+            .collect();
+
+        page(requests, from, (to - from).max(1))
+    }
+
+    async fn get_private_block(
+        &self,
+        from_user_id: &str,
+        to_user_id: &str,
+    ) -> Option<PrivateBlocked> {
+        self.tables()
+            .blocks
+            .iter()
+            .find(|b| b.from_user_id == from_user_id && b.to_user_id == to_user_id)
+            .map(|b| PrivateBlocked {
+                from_user_id: b.from_user_id.clone(),
+                to_user_id: b.to_user_id.clone(),
+                created_at: b.created_at,
+            })
+    }
+
+    async fn get_public_blocks(
+        &self,
+        from_user_id: &str,
+        from: i64,
+        to: i64,
+    ) -> Option<Vec<PublicBlocked>> {
+        let tables = self.tables();
+
+        let blocks = tables
+            .blocks
+            .iter()
+            .rev()
+            .filter(|b| b.from_user_id == from_user_id)
+            .filter_map(|b| {
+                Some(PublicBlocked {
+                    username: tables.username(&b.to_user_id)?,
+                    created_at: b.created_at,
+                })
+            })
+            .collect();
+
+        page(blocks, from, to)
+    }
+
+    async fn insert_friend_request(
+        &self,
+        from_user_id: &str,
+        to_user_id: &str,
+        event: OutboxEvent,
+    ) -> anyhow::Result<()> {
+        let mut tables = self.tables();
+
+        if from_user_id == to_user_id {
+            bail!("Users cannot request themselves");
+        }
+
+        if tables.username(from_user_id).is_none() || tables.username(to_user_id).is_none() {
+            bail!("User does not exist");
+        }
+
+        let pending = FriendRequestState::Pending.to_string();
+        if tables.friend_requests.iter().any(|r| {
+            r.from_user_id == from_user_id && r.to_user_id == to_user_id && r.state == pending
+        }) {
+            bail!("Request already pending");
+        }
+
+        tables.friend_requests.push(PrivateFriendRequest {
+            from_user_id: from_user_id.to_owned(),
+            to_user_id: to_user_id.to_owned(),
+            state: pending,
+            created_at: Some(Utc::now()),
+            responded_at: None,
+        });
+        tables.push_outbox(event);
+
+        Ok(())
+    }
+
+    async fn accept_friend_request(
+        &self,
+        from_user_id: &str,
+        to_user_id: &str,
+        event: OutboxEvent,
+    ) -> anyhow::Result<()> {
+        let mut tables = self.tables();
+
+        if tables
+            .friendships
+            .iter()
+            .any(|f| f.from_user_id == from_user_id && f.to_user_id == to_user_id)
+        {
+            bail!("Friendship already exists");
+        }
+
+        tables.answer_request(from_user_id, to_user_id, FriendRequestState::Accepted)?;
+
+        let now = Some(Utc::now());
+        tables.friendships.push(PrivateFriendship {
+            from_user_id: from_user_id.to_owned(),
+            to_user_id: to_user_id.to_owned(),
+            created_at: now,
+        });
+        tables.friendships.push(PrivateFriendship {
+            from_user_id: to_user_id.to_owned(),
+            to_user_id: from_user_id.to_owned(),
+            created_at: now,
+        });
+        tables.push_outbox(event);
+
+        Ok(())
+    }
+
+    async fn accept_mutual_friend_requests(
+        &self,
+        from_user_id: &str,
+        to_user_id: &str,
+        events: Vec<OutboxEvent>,
+    ) -> anyhow::Result<()> {
+        let mut tables = self.tables();
+
+        tables.answer_request(to_user_id, from_user_id, FriendRequestState::Accepted)?;
+
+        if tables.has_pending_request(from_user_id, to_user_id) {
+            tables.answer_request(from_user_id, to_user_id, FriendRequestState::Accepted)?;
+        } else {
+            let now = Some(Utc::now());
+            tables.friend_requests.push(PrivateFriendRequest {
+                from_user_id: from_user_id.to_owned(),
+                to_user_id: to_user_id.to_owned(),
+                state: FriendRequestState::Accepted.to_string(),
+                created_at: now,
+                responded_at: now,
+            });
+        }
+
+        let now = Some(Utc::now());
+        tables.friendships.push(PrivateFriendship {
+            from_user_id: from_user_id.to_owned(),
+            to_user_id: to_user_id.to_owned(),
+            created_at: now,
+        });
+        tables.friendships.push(PrivateFriendship {
+            from_user_id: to_user_id.to_owned(),
+            to_user_id: from_user_id.to_owned(),
+            created_at: now,
+        });
+
+        for event in events {
+            tables.push_outbox(event);
+        }
+
+        Ok(())
+    }
+
+    async fn reject_friend_request(
+        &self,
+        from_user_id: &str,
+        to_user_id: &str,
+        event: OutboxEvent,
+    ) -> anyhow::Result<()> {
+        let mut tables = self.tables();
+
+        tables.answer_request(from_user_id, to_user_id, FriendRequestState::Rejected)?;
+        tables.push_outbox(event);
+
+        Ok(())
+    }
+
+    async fn cancel_friend_request(
+        &self,
+        from_user_id: &str,
+        to_user_id: &str,
+        event: OutboxEvent,
+    ) -> anyhow::Result<()> {
+        let mut tables = self.tables();
+
+        tables.answer_request(from_user_id, to_user_id, FriendRequestState::Cancelled)?;
+        tables.push_outbox(event);
+
+        Ok(())
+    }
+
+    async fn delete_friendship(
+        &self,
+        a_user_id: &str,
+        b_user_id: &str,
+        event: OutboxEvent,
+    ) -> anyhow::Result<()> {
+        let mut tables = self.tables();
+
+        let between = |from: &str, to: &str| {
+            (from == a_user_id && to == b_user_id) || (from == b_user_id && to == a_user_id)
+        };
+
+        tables
+            .friendships
+            .retain(|f| !between(&f.from_user_id, &f.to_user_id));
+
+        let accepted = FriendRequestState::Accepted.to_string();
+        for request in tables.friend_requests.iter_mut() {
+            if between(&request.from_user_id, &request.to_user_id) && request.state == accepted {
+                request.state = FriendRequestState::Removed.to_string();
+                request.responded_at = Some(Utc::now());
+            }
+        }
+
+        tables.push_outbox(event);
+
+        Ok(())
+    }
+
+    async fn insert_block(&self, block: PrivateBlocked) -> anyhow::Result<()> {
+        let mut tables = self.tables();
+
+        if block.from_user_id == block.to_user_id {
+            bail!("Users cannot block themselves");
+        }
+
+        if tables
+            .blocks
+            .iter()
+            .any(|b| b.from_user_id == block.from_user_id && b.to_user_id == block.to_user_id)
+        {
+            bail!("Block already exists");
+        }
+
+        let between = |from: &str, to: &str| {
+            (from == block.from_user_id && to == block.to_user_id)
+                || (from == block.to_user_id && to == block.from_user_id)
+        };
+
+        // Requests are kept as history, answered in both directions at once
+        let pending = FriendRequestState::Pending.to_string();
+        let accepted = FriendRequestState::Accepted.to_string();
+
+        for request in tables
+            .friend_requests
+            .iter_mut()
+            .filter(|r| between(&r.from_user_id, &r.to_user_id))
+        {
+            if request.state == pending {
+                request.state = FriendRequestState::Cancelled.to_string();
+                request.responded_at = Some(Utc::now());
+            } else if request.state == accepted {
+                request.state = FriendRequestState::Removed.to_string();
+                request.responded_at = Some(Utc::now());
+            }
+        }
+
+        tables
+            .friendships
+            .retain(|f| !between(&f.from_user_id, &f.to_user_id));
+
+        tables.blocks.push(PrivateBlocked {
+            created_at: Some(Utc::now()),
+            ..block
+        });
+
+        Ok(())
+    }
+
+    async fn delete_block(&self, block: PrivateBlocked) -> anyhow::Result<()> {
+        self.tables().blocks.retain(|b| {
+            !(b.from_user_id == block.from_user_id && b.to_user_id == block.to_user_id)
+        });
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl OutboxRepository for MemoryRepository {
+    async fn relay_outbox(
+        &self,
+        publisher: &dyn EventPublisher,
+        batch_size: i64,
+        _lease_secs: i64,
+        _max_backoff_secs: i64,
+    ) -> anyhow::Result<usize> {
+        let pending: Vec<PrivateOutboxEvent> = {
+            let tables = self.tables();
+            tables
+                .outbox
+                .iter()
+                .filter(|e| !tables.published.contains(&e.id))
+                .take(batch_size.max(0) as usize)
+                .cloned()
+                .collect()
+        };
+
+        let mut failed_keys: Vec<&str> = Vec::new();
+
+        for event in pending.iter() {
+            if failed_keys.contains(&event.key.as_str()) {
+                continue;
+            }
+
+            let result = publisher
+                .publish(&event.topic, &event.key, &event.payload)
+                .await;
+
+            let mut tables = self.tables();
+            match result {
+                Ok(_) => tables.published.push(event.id),
+                Err(_) => {
+                    failed_keys.push(&event.key);
+                    if let Some(e) = tables.outbox.iter_mut().find(|e| e.id == event.id) {
+                        e.attempts += 1;
+                    }
+                }
+            }
+        }
+
+        Ok(pending.len())
+    }
+}
+
+#[async_trait]
+impl ConsumerOffsetRepository for MemoryRepository {
+    async fn get_consumer_offset(
+        &self,
+        consumer: &str,
+        topic: &str,
+    ) -> anyhow::Result<Option<i64>> {
+        self.injected_failure("get_consumer_offset")?;
+
+        Ok(self
+            .tables()
+            .consumer_offsets
+            .get(&(consumer.to_owned(), topic.to_owned()))
+            .copied())
+    }
+
+    async fn commit_consumer_offset(&self, offset: ConsumerOffset) -> anyhow::Result<()> {
+        self.injected_failure("commit_consumer_offset")?;
+
+        self.tables().commit_offset(offset);
+
+        Ok(())
+    }
+
+    async fn dead_letter(&self, event: OutboxEvent, offset: ConsumerOffset) -> anyhow::Result<()> {
+        self.injected_failure("dead_letter")?;
+
+        let mut tables = self.tables();
+
+        tables.push_outbox(event);
+        tables.commit_offset(offset);
+
+        Ok(())
+    }
+}
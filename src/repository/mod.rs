@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::{
+    api_utils::structs::{
+        ConsumerOffset, OutboxEvent, PrivateBlocked, PrivateFriendRequest, PrivateFriendship,
+        PrivateProfile, PrivateUser, PublicBlocked, PublicFriendRequestReceived,
+        PublicFriendRequestSent, PublicFriendSuggestion, PublicFriendship, PublicUser,
+        RequestUpdateProfileEnum, UserSettings,
+    },
+    event_bus::EventPublisher,
+};
+
+pub mod memory;
+pub mod postgres;
+
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    async fn get_public_user(&self, id: &str) -> Option<PublicUser>;
+
+    /// Same lookup as `get_public_user`, but a failed query is an error rather than `None`
+    async fn find_public_user(&self, id: &str) -> anyhow::Result<Option<PublicUser>>;
+
+    /// Whether `delete_user` ever removed `id`, such a user must not come back
+    async fn is_user_purged(&self, id: &str) -> anyhow::Result<bool>;
+
+    async fn get_private_user(&self, username: &str) -> Option<PrivateUser>;
+
+    /// Any user whose username has the same confusable skeleton as `username`
+    async fn get_confusable_user(&self, username: &str) -> Option<PrivateUser>;
+
+    async fn get_private_profile(&self, username: &str) -> Option<PrivateProfile>;
+
+    async fn get_last_username_change(&self, user_id: &str) -> Option<DateTime<Utc>>;
+
+    /// None until the user changes a setting, the defaults apply then
+    async fn get_settings(&self, user_id: &str) -> Option<UserSettings>;
+
+    async fn update_settings(&self, user_id: &str, settings: UserSettings) -> anyhow::Result<()>;
+
+    /// Current owner of the latest user who released `username` after `since`
+    async fn get_previous_username_owner(
+        &self,
+        username: &str,
+        since: DateTime<Utc>,
+    ) -> Option<PrivateUser>;
+
+    /// `event` and `offset` are written in the same transaction when given,
+    /// `offset` being set when the user comes from a consumer
+    async fn insert_user(
+        &self,
+        user: PrivateUser,
+        event: Option<OutboxEvent>,
+        offset: Option<ConsumerOffset>,
+    ) -> anyhow::Result<()>;
+
+    /// Hard deletes the user with everything referencing it, leaves a tombstone for the id and
+    /// queues `event` atomically, along with `offset` when the deletion comes from a consumer
+    async fn delete_user(
+        &self,
+        id: &str,
+        event: OutboxEvent,
+        offset: Option<ConsumerOffset>,
+    ) -> anyhow::Result<()>;
+
+    /// Leaves the tombstone of a user deleted before it was ever created here and commits
+    /// `offset` atomically, so the late registration is refused
+    async fn insert_purged_user(&self, id: &str, offset: ConsumerOffset) -> anyhow::Result<()>;
+
+    /// Applies every change or none of them and queues `event` with them, a `None` value
+    /// clears the field. Renaming keeps the old username in the history
+    async fn update_profile(
+        &self,
+        id: &str,
+        changes: &HashMap<RequestUpdateProfileEnum, Option<String>>,
+        event: OutboxEvent,
+    ) -> anyhow::Result<()>;
+}
+
+#[async_trait]
+pub trait SocialGraphRepository: Send + Sync {
+    async fn get_private_friendship(
+        &self,
+        from_user_id: &str,
+        to_user_id: &str,
+    ) -> Option<PrivateFriendship>;
+
+    async fn get_public_friendships(
+        &self,
+        from_user_id: &str,
+        from: i64,
+        to: i64,
+    ) -> Option<Vec<PublicFriendship>>;
+
+    /// Friends of `user_id` who are also friends of `other_user_id`, with the date
+    /// `user_id` befriended them
+    async fn get_public_mutual_friendships(
+        &self,
+        user_id: &str,
+        other_user_id: &str,
+        from: i64,
+        to: i64,
+    ) -> Option<Vec<PublicFriendship>>;
+
+    /// Non friends ranked by how many friends they share with `user_id`, leaving out
+    /// blocks in either direction, pending requests and users refusing every request
+    async fn get_public_friend_suggestions(
+        &self,
+        user_id: &str,
+        from: i64,
+        to: i64,
+    ) -> Option<Vec<PublicFriendSuggestion>>;
+
+    /// At most `limit` friendships towards `user_ids` from users whose friends `viewer_id`
+    /// may see, that is its friends and public lists not blocked either way
+    async fn get_traversable_friendships(
+        &self,
+        viewer_id: &str,
+        user_ids: &[String],
+        limit: i64,
+    ) -> Option<Vec<PrivateFriendship>>;
+
+    /// Whether both users are friends with at least one same user
+    async fn has_mutual_friend(&self, user_id: &str, other_user_id: &str) -> bool;
+
+    /// Latest request sent from `from_user_id` to `to_user_id`, older ones are kept as history
+    async fn get_private_friend_request(
+        &self,
+        from_user_id: &str,
+        to_user_id: &str,
+    ) -> Option<PrivateFriendRequest>;
+
+    /// Latest request between both users, whoever sent it
+    async fn get_latest_friend_request(
+        &self,
+        a_user_id: &str,
+        b_user_id: &str,
+    ) -> Option<PrivateFriendRequest>;
+
+    async fn get_public_friend_requests_received(
+        &self,
+        to_user_id: &str,
+        from: i64,
+        to: i64,
+    ) -> Option<Vec<PublicFriendRequestReceived>>;
+
+    async fn get_public_friend_requests_sent(
+        &self,
+        from_user_id: &str,
+        from: i64,
+        to: i64,
+    ) -> Option<Vec<PublicFriendRequestSent>>;
+
+    async fn get_private_block(
+        &self,
+        from_user_id: &str,
+        to_user_id: &str,
+    ) -> Option<PrivateBlocked>;
+
+    async fn get_public_blocks(
+        &self,
+        from_user_id: &str,
+        from: i64,
+        to: i64,
+    ) -> Option<Vec<PublicBlocked>>;
+
+    /// Creates a pending request and queues `event` in the same transaction
+    async fn insert_friend_request(
+        &self,
+        from_user_id: &str,
+        to_user_id: &str,
+        event: OutboxEvent,
+    ) -> anyhow::Result<()>;
+
+    /// Marks the request accepted, creates the friendship and queues `event` atomically
+    async fn accept_friend_request(
+        &self,
+        from_user_id: &str,
+        to_user_id: &str,
+        event: OutboxEvent,
+    ) -> anyhow::Result<()>;
+
+    /// Both users requested each other, accepts the pending request from `to_user_id`,
+    /// records the one from `from_user_id` as accepted and creates the friendship atomically
+    async fn accept_mutual_friend_requests(
+        &self,
+        from_user_id: &str,
+        to_user_id: &str,
+        events: Vec<OutboxEvent>,
+    ) -> anyhow::Result<()>;
+
+    /// Marks the request rejected and queues `event` atomically
+    async fn reject_friend_request(
+        &self,
+        from_user_id: &str,
+        to_user_id: &str,
+        event: OutboxEvent,
+    ) -> anyhow::Result<()>;
+
+    /// Marks the pending request cancelled and queues `event` atomically
+    async fn cancel_friend_request(
+        &self,
+        from_user_id: &str,
+        to_user_id: &str,
+        event: OutboxEvent,
+    ) -> anyhow::Result<()>;
+
+    /// Drops the friendship, marks the accepted request removed and queues `event` atomically
+    async fn delete_friendship(
+        &self,
+        a_user_id: &str,
+        b_user_id: &str,
+        event: OutboxEvent,
+    ) -> anyhow::Result<()>;
+
+    /// Adds the block and drops any friendship or request between both users
+    async fn insert_block(&self, block: PrivateBlocked) -> anyhow::Result<()>;
+
+    async fn delete_block(&self, block: PrivateBlocked) -> anyhow::Result<()>;
+}
+
+#[async_trait]
+pub trait OutboxRepository: Send + Sync {
+    /// Publishes up to `batch_size` pending events and returns how many were attempted.
+    /// Events are leased for `lease_secs` while being published, and keep their order
+    /// per key: an event is held back while an earlier one with the same key is unpublished
+    async fn relay_outbox(
+        &self,
+        publisher: &dyn EventPublisher,
+        batch_size: i64,
+        lease_secs: i64,
+        max_backoff_secs: i64,
+    ) -> anyhow::Result<usize>;
+}
+
+#[async_trait]
+pub trait ConsumerOffsetRepository: Send + Sync {
+    /// Next offset `consumer` has to read from `topic`, `None` if it never committed one
+    async fn get_consumer_offset(&self, consumer: &str, topic: &str)
+    -> anyhow::Result<Option<i64>>;
+
+    /// Commits an offset on its own, for records that change nothing
+    async fn commit_consumer_offset(&self, offset: ConsumerOffset) -> anyhow::Result<()>;
+
+    /// Queues the dead letter `event` and commits `offset` past the failed record atomically
+    async fn dead_letter(&self, event: OutboxEvent, offset: ConsumerOffset) -> anyhow::Result<()>;
+}
+
+/// Whether the same operation may succeed if retried, a lost connection or a deadlock
+/// rather than a constraint violation
+pub fn is_transient(error: &anyhow::Error) -> bool {
+    match error.downcast_ref::<sqlx::Error>() {
+        Some(sqlx::Error::Io(_))
+        | Some(sqlx::Error::PoolTimedOut)
+        | Some(sqlx::Error::PoolClosed)
+        | Some(sqlx::Error::WorkerCrashed) => true,
+        // Connection exceptions and transaction rollbacks (serialization failure, deadlock)
+        Some(sqlx::Error::Database(e)) => e
+            .code()
+            .is_some_and(|code| code.starts_with("08") || code.starts_with("40")),
+        _ => false,
+    }
+}
+
+pub trait Repository:
+    UserRepository + SocialGraphRepository + OutboxRepository + ConsumerOffsetRepository
+{
+}
+
+impl<T> Repository for T where
+    T: UserRepository + SocialGraphRepository + OutboxRepository + ConsumerOffsetRepository
+{
+}
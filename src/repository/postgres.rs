@@ -0,0 +1,521 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::{
+    api_utils::structs::{
+        ConsumerOffset, FriendRequestState, OutboxEvent, PrivateBlocked, PrivateFriendRequest,
+        PrivateFriendship, PrivateProfile, PrivateUser, PublicBlocked, PublicFriendRequestReceived,
+        PublicFriendRequestSent, PublicFriendSuggestion, PublicFriendship, PublicUser,
+        RequestUpdateProfileEnum, UserSettings,
+    },
+    event_bus::EventPublisher,
+    policy::username::{normalize_username, username_skeleton},
+    repository::{
+        ConsumerOffsetRepository, OutboxRepository, SocialGraphRepository, UserRepository,
+    },
+    sql_utils::calls,
+};
+
+#[derive(Clone)]
+pub struct PgRepository {
+    db: sqlx::PgPool,
+}
+
+impl PgRepository {
+    pub fn new(db: sqlx::PgPool) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait]
+impl UserRepository for PgRepository {
+    async fn get_public_user(&self, id: &str) -> Option<PublicUser> {
+        calls::get_public_user(id, &self.db).await
+    }
+
+    async fn find_public_user(&self, id: &str) -> anyhow::Result<Option<PublicUser>> {
+        calls::find_public_user(id, &self.db).await
+    }
+
+    async fn is_user_purged(&self, id: &str) -> anyhow::Result<bool> {
+        calls::get_user_purged(id, &self.db).await
+    }
+
+    async fn get_private_user(&self, username: &str) -> Option<PrivateUser> {
+        calls::get_private_user(&normalize_username(username), &self.db).await
+    }
+
+    async fn get_confusable_user(&self, username: &str) -> Option<PrivateUser> {
+        let skeleton = username_skeleton(&normalize_username(username));
+        calls::get_user_by_skeleton(&skeleton, &self.db).await
+    }
+
+    async fn get_private_profile(&self, username: &str) -> Option<PrivateProfile> {
+        calls::get_private_profile(&normalize_username(username), &self.db).await
+    }
+
+    async fn get_last_username_change(&self, user_id: &str) -> Option<DateTime<Utc>> {
+        calls::get_last_username_change(user_id, &self.db).await
+    }
+
+    async fn get_settings(&self, user_id: &str) -> Option<UserSettings> {
+        calls::get_private_settings(user_id, &self.db)
+            .await
+            .map(UserSettings::from)
+    }
+
+    async fn update_settings(&self, user_id: &str, settings: UserSettings) -> anyhow::Result<()> {
+        calls::update_user_settings(user_id, &settings, &self.db).await
+    }
+
+    async fn get_previous_username_owner(
+        &self,
+        username: &str,
+        since: DateTime<Utc>,
+    ) -> Option<PrivateUser> {
+        calls::get_previous_username_owner(&normalize_username(username), since, &self.db).await
+    }
+
+    async fn insert_user(
+        &self,
+        user: PrivateUser,
+        event: Option<OutboxEvent>,
+        offset: Option<ConsumerOffset>,
+    ) -> anyhow::Result<()> {
+        let mut tx = self.db.begin().await?;
+
+        let skeleton = username_skeleton(&user.username);
+        calls::insert_user(user, &skeleton, &mut *tx).await?;
+
+        if let Some(event) = event {
+            calls::insert_outbox_event(&event.topic, &event.key, event.payload, &mut *tx).await?;
+        }
+
+        if let Some(offset) = offset {
+            calls::update_consumer_offset(offset, &mut *tx).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn delete_user(
+        &self,
+        id: &str,
+        event: OutboxEvent,
+        offset: Option<ConsumerOffset>,
+    ) -> anyhow::Result<()> {
+        let mut tx = self.db.begin().await?;
+
+        calls::delete_user(id, &mut *tx).await?;
+        calls::insert_purged_user(id, &mut *tx).await?;
+        calls::insert_outbox_event(&event.topic, &event.key, event.payload, &mut *tx).await?;
+
+        if let Some(offset) = offset {
+            calls::update_consumer_offset(offset, &mut *tx).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn insert_purged_user(&self, id: &str, offset: ConsumerOffset) -> anyhow::Result<()> {
+        let mut tx = self.db.begin().await?;
+
+        calls::insert_purged_user(id, &mut *tx).await?;
+        calls::update_consumer_offset(offset, &mut *tx).await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn update_profile(
+        &self,
+        id: &str,
+        changes: &HashMap<RequestUpdateProfileEnum, Option<String>>,
+        event: OutboxEvent,
+    ) -> anyhow::Result<()> {
+        let mut tx = self.db.begin().await?;
+
+        for (part, value) in changes.iter() {
+            if let (RequestUpdateProfileEnum::Username, Some(username)) = (part, value) {
+                calls::insert_username_history(id, username, &mut *tx).await?;
+            }
+
+            calls::update_user_profile_field(id, part, value.as_deref(), &mut *tx).await?;
+
+            if let (RequestUpdateProfileEnum::Username, Some(username)) = (part, value) {
+                calls::update_user_username_skeleton(id, &username_skeleton(username), &mut *tx)
+                    .await?;
+            }
+        }
+
+        calls::insert_outbox_event(&event.topic, &event.key, event.payload, &mut *tx).await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SocialGraphRepository for PgRepository {
+    async fn get_private_friendship(
+        &self,
+        from_user_id: &str,
+        to_user_id: &str,
+    ) -> Option<PrivateFriendship> {
+        calls::get_private_friendship(from_user_id, to_user_id, &self.db).await
+    }
+
+    async fn get_public_friendships(
+        &self,
+        from_user_id: &str,
+        from: i64,
+        to: i64,
+    ) -> Option<Vec<PublicFriendship>> {
+        calls::get_public_friendships(from_user_id, from, to, &self.db).await
+    }
+
+    async fn get_public_mutual_friendships(
+        &self,
+        user_id: &str,
+        other_user_id: &str,
+        from: i64,
+        to: i64,
+    ) -> Option<Vec<PublicFriendship>> {
+        calls::get_public_mutual_friendships(user_id, other_user_id, from, to, &self.db).await
+    }
+
+    async fn get_public_friend_suggestions(
+        &self,
+        user_id: &str,
+        from: i64,
+        to: i64,
+    ) -> Option<Vec<PublicFriendSuggestion>> {
+        calls::get_public_friend_suggestions(user_id, from, to, &self.db).await
+    }
+
+    async fn get_traversable_friendships(
+        &self,
+        viewer_id: &str,
+        user_ids: &[String],
+        limit: i64,
+    ) -> Option<Vec<PrivateFriendship>> {
+        calls::get_traversable_friendships(viewer_id, user_ids, limit, &self.db).await
+    }
+
+    async fn has_mutual_friend(&self, user_id: &str, other_user_id: &str) -> bool {
+        calls::get_mutual_friend_exists(user_id, other_user_id, &self.db).await
+    }
+
+    async fn get_private_friend_request(
+        &self,
+        from_user_id: &str,
+        to_user_id: &str,
+    ) -> Option<PrivateFriendRequest> {
+        calls::get_private_friend_request(from_user_id, to_user_id, &self.db).await
+    }
+
+    async fn get_latest_friend_request(
+        &self,
+        a_user_id: &str,
+        b_user_id: &str,
+    ) -> Option<PrivateFriendRequest> {
+        calls::get_latest_undirected_private_friend_request(a_user_id, b_user_id, &self.db).await
+    }
+
+    async fn get_public_friend_requests_received(
+        &self,
+        to_user_id: &str,
+        from: i64,
+        to: i64,
+    ) -> Option<Vec<PublicFriendRequestReceived>> {
+        calls::get_public_friend_requests_received(to_user_id, from, to, &self.db).await
+    }
+
+    async fn get_public_friend_requests_sent(
+        &self,
+        from_user_id: &str,
+        from: i64,
+        to: i64,
+    ) -> Option<Vec<PublicFriendRequestSent>> {
+        calls::get_public_friend_requests_sent(from_user_id, from, to, &self.db).await
+    }
+
+    async fn get_private_block(
+        &self,
+        from_user_id: &str,
+        to_user_id: &str,
+    ) -> Option<PrivateBlocked> {
+        calls::get_private_block(from_user_id, to_user_id, &self.db).await
+    }
+
+    async fn get_public_blocks(
+        &self,
+        from_user_id: &str,
+        from: i64,
+        to: i64,
+    ) -> Option<Vec<PublicBlocked>> {
+        calls::get_public_blocks(from_user_id, from, to, &self.db).await
+    }
+
+    async fn insert_friend_request(
+        &self,
+        from_user_id: &str,
+        to_user_id: &str,
+        event: OutboxEvent,
+    ) -> anyhow::Result<()> {
+        let mut tx = self.db.begin().await?;
+
+        calls::insert_friend_request(from_user_id, to_user_id, &mut *tx).await?;
+        calls::insert_outbox_event(&event.topic, &event.key, event.payload, &mut *tx).await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn accept_friend_request(
+        &self,
+        from_user_id: &str,
+        to_user_id: &str,
+        event: OutboxEvent,
+    ) -> anyhow::Result<()> {
+        let mut tx = self.db.begin().await?;
+
+        let request = PrivateFriendRequest {
+            from_user_id: from_user_id.to_owned(),
+            to_user_id: to_user_id.to_owned(),
+            state: FriendRequestState::Accepted.to_string(),
+            created_at: None,
+            responded_at: None,
+        };
+
+        calls::update_friend_request_state(request, &mut *tx).await?;
+        calls::insert_friendship(from_user_id, to_user_id, &mut *tx).await?;
+        calls::insert_outbox_event(&event.topic, &event.key, event.payload, &mut *tx).await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn accept_mutual_friend_requests(
+        &self,
+        from_user_id: &str,
+        to_user_id: &str,
+        events: Vec<OutboxEvent>,
+    ) -> anyhow::Result<()> {
+        let mut tx = self.db.begin().await?;
+
+        let accepted = |from: &str, to: &str| PrivateFriendRequest {
+            from_user_id: from.to_owned(),
+            to_user_id: to.to_owned(),
+            state: FriendRequestState::Accepted.to_string(),
+            created_at: None,
+            responded_at: None,
+        };
+
+        calls::update_friend_request_state(accepted(to_user_id, from_user_id), &mut *tx).await?;
+
+        // Reuse the caller's pending request if there is one, otherwise record a new one
+        let pending =
+            calls::get_pending_friend_request_for_update(from_user_id, to_user_id, &mut *tx)
+                .await?;
+
+        if pending.is_none() {
+            calls::insert_friend_request(from_user_id, to_user_id, &mut *tx).await?;
+        }
+
+        calls::update_friend_request_state(accepted(from_user_id, to_user_id), &mut *tx).await?;
+
+        calls::insert_friendship(from_user_id, to_user_id, &mut *tx).await?;
+
+        for event in events {
+            calls::insert_outbox_event(&event.topic, &event.key, event.payload, &mut *tx).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn reject_friend_request(
+        &self,
+        from_user_id: &str,
+        to_user_id: &str,
+        event: OutboxEvent,
+    ) -> anyhow::Result<()> {
+        let mut tx = self.db.begin().await?;
+
+        let request = PrivateFriendRequest {
+            from_user_id: from_user_id.to_owned(),
+            to_user_id: to_user_id.to_owned(),
+            state: FriendRequestState::Rejected.to_string(),
+            created_at: None,
+            responded_at: None,
+        };
+
+        calls::update_friend_request_state(request, &mut *tx).await?;
+        calls::insert_outbox_event(&event.topic, &event.key, event.payload, &mut *tx).await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn cancel_friend_request(
+        &self,
+        from_user_id: &str,
+        to_user_id: &str,
+        event: OutboxEvent,
+    ) -> anyhow::Result<()> {
+        let mut tx = self.db.begin().await?;
+
+        let request = PrivateFriendRequest {
+            from_user_id: from_user_id.to_owned(),
+            to_user_id: to_user_id.to_owned(),
+            state: FriendRequestState::Cancelled.to_string(),
+            created_at: None,
+            responded_at: None,
+        };
+
+        calls::update_friend_request_state(request, &mut *tx).await?;
+        calls::insert_outbox_event(&event.topic, &event.key, event.payload, &mut *tx).await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn delete_friendship(
+        &self,
+        a_user_id: &str,
+        b_user_id: &str,
+        event: OutboxEvent,
+    ) -> anyhow::Result<()> {
+        let mut tx = self.db.begin().await?;
+
+        let friendship = PrivateFriendship {
+            from_user_id: a_user_id.to_owned(),
+            to_user_id: b_user_id.to_owned(),
+            created_at: None,
+        };
+
+        calls::delete_friendship(friendship, &mut *tx).await?;
+        calls::update_friend_requests_removed(a_user_id, b_user_id, &mut *tx).await?;
+        calls::insert_outbox_event(&event.topic, &event.key, event.payload, &mut *tx).await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn insert_block(&self, block: PrivateBlocked) -> anyhow::Result<()> {
+        let mut tx = self.db.begin().await?;
+
+        let from_user_id = block.from_user_id.clone();
+        let to_user_id = block.to_user_id.clone();
+
+        calls::insert_block(block, &mut *tx).await?;
+
+        // Requests are kept as history, answered in both directions at once
+        calls::update_friend_requests_cancelled(&from_user_id, &to_user_id, &mut *tx).await?;
+        calls::update_friend_requests_removed(&from_user_id, &to_user_id, &mut *tx).await?;
+
+        if let Some(friendship) =
+            calls::get_private_friendship(&from_user_id, &to_user_id, &mut *tx).await
+        {
+            calls::delete_friendship(friendship, &mut *tx).await?;
+        };
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn delete_block(&self, block: PrivateBlocked) -> anyhow::Result<()> {
+        calls::delete_block(block, &self.db).await
+    }
+}
+
+#[async_trait]
+impl OutboxRepository for PgRepository {
+    async fn relay_outbox(
+        &self,
+        publisher: &dyn EventPublisher,
+        batch_size: i64,
+        lease_secs: i64,
+        max_backoff_secs: i64,
+    ) -> anyhow::Result<usize> {
+        // No transaction or connection is held while publishing, a slow broker must not
+        // starve the handlers of the pool
+        let events = calls::update_outbox_events_claimed(batch_size, lease_secs, &self.db).await?;
+        let mut failed_keys: Vec<&str> = Vec::new();
+
+        for event in events.iter() {
+            // Left leased, the failed event holds it back once its lease expires
+            if failed_keys.contains(&event.key.as_str()) {
+                continue;
+            }
+
+            let result = publisher
+                .publish(&event.topic, &event.key, &event.payload)
+                .await;
+
+            match result {
+                Ok(_) => calls::update_outbox_event_published(event.id, &self.db).await?,
+                Err(e) => {
+                    failed_keys.push(&event.key);
+                    tracing::warn!(
+                        "Failed to publish outbox event {} to {} (attempt {}): {e:?}",
+                        event.id,
+                        event.topic,
+                        event.attempts + 1
+                    );
+                    calls::update_outbox_event_failed(
+                        event.id,
+                        &e.to_string(),
+                        max_backoff_secs,
+                        &self.db,
+                    )
+                    .await?
+                }
+            }
+        }
+
+        Ok(events.len())
+    }
+}
+
+#[async_trait]
+impl ConsumerOffsetRepository for PgRepository {
+    async fn get_consumer_offset(
+        &self,
+        consumer: &str,
+        topic: &str,
+    ) -> anyhow::Result<Option<i64>> {
+        calls::get_consumer_offset(consumer, topic, &self.db).await
+    }
+
+    async fn commit_consumer_offset(&self, offset: ConsumerOffset) -> anyhow::Result<()> {
+        calls::update_consumer_offset(offset, &self.db).await
+    }
+
+    async fn dead_letter(&self, event: OutboxEvent, offset: ConsumerOffset) -> anyhow::Result<()> {
+        let mut tx = self.db.begin().await?;
+
+        calls::insert_outbox_event(&event.topic, &event.key, event.payload, &mut *tx).await?;
+        calls::update_consumer_offset(offset, &mut *tx).await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
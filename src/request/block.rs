@@ -14,11 +14,6 @@ use crate::{
     },
     app::AppState,
     jwt::Claims,
-    sql_utils::calls::{
-        delete_block, delete_friend_request, delete_friendship, get_private_block,
-        get_private_friendship, get_private_user, get_public_blocks, get_public_user,
-        get_undirected_private_friend_requests, insert_block,
-    },
 };
 
 pub async fn block_user(
@@ -26,16 +21,18 @@ pub async fn block_user(
     claims: Claims,
     Json(body): Json<RequestUserBlock>,
 ) -> impl IntoResponse {
-    if get_public_user(&claims.user_id, &state.db).await.is_none() {
+    if state.repo.get_public_user(&claims.user_id).await.is_none() {
         return responses::USER_DOES_NOT_EXIST;
     }
 
-    let to_user = match get_private_user(&body.to_user_username, &state.db).await {
+    let to_user = match state.repo.get_private_user(&body.to_user_username).await {
         Some(e) => e,
         None => return responses::USER_DOES_NOT_EXIST,
     };
 
-    if get_private_block(&claims.user_id, &to_user.id, &state.db)
+    if state
+        .repo
+        .get_private_block(&claims.user_id, &to_user.id)
         .await
         .is_some()
     {
@@ -48,28 +45,10 @@ pub async fn block_user(
         created_at: None,
     };
 
-    if insert_block(block, &state.db).await.is_err() {
+    if state.repo.insert_block(block).await.is_err() {
         return responses::DB_ERROR;
     }
 
-    // Prob should make this better but im too lazy rn
-    if let Some(mut requests) =
-        get_undirected_private_friend_requests(&claims.user_id, &to_user.id, &state.db).await
-    {
-        if let Some(request) = requests.pop() {
-            if delete_friend_request(request, &state.db).await.is_err() {
-                return responses::DB_ERROR;
-            }
-        }
-    };
-
-    if let Some(friendship) = get_private_friendship(&claims.user_id, &to_user.id, &state.db).await
-    {
-        if delete_friendship(friendship, &state.db).await.is_err() {
-            return responses::DB_ERROR;
-        }
-    };
-
     responses::BLOCK_ADDED
 }
 
@@ -78,21 +57,25 @@ pub async fn unblock_user(
     claims: Claims,
     Json(body): Json<RequestUserBlock>,
 ) -> impl IntoResponse {
-    if get_public_user(&claims.user_id, &state.db).await.is_none() {
+    if state.repo.get_public_user(&claims.user_id).await.is_none() {
         return responses::USER_DOES_NOT_EXIST;
     }
 
-    let to_user = match get_private_user(&body.to_user_username, &state.db).await {
+    let to_user = match state.repo.get_private_user(&body.to_user_username).await {
         Some(e) => e,
         None => return responses::USER_DOES_NOT_EXIST,
     };
 
-    let block = match get_private_block(&claims.user_id, &to_user.id, &state.db).await {
+    let block = match state
+        .repo
+        .get_private_block(&claims.user_id, &to_user.id)
+        .await
+    {
         Some(e) => e,
         None => return responses::BLOCK_DOES_NOT_EXISTS,
     };
 
-    if delete_block(block, &state.db).await.is_err() {
+    if state.repo.delete_block(block).await.is_err() {
         return responses::DB_ERROR;
     }
 
@@ -104,11 +87,14 @@ pub async fn get_blocked(
     claims: Claims,
     Query(query): Query<RequestUsersBlocked>,
 ) -> Either<Json<Option<Vec<PublicBlocked>>>, impl IntoResponse> {
-    if get_public_user(&claims.user_id, &state.db).await.is_none() {
+    if state.repo.get_public_user(&claims.user_id).await.is_none() {
         return E2(responses::USER_DOES_NOT_EXIST);
     }
 
-    let requests = get_public_blocks(&claims.user_id, query.from, query.to, &state.db).await;
+    let requests = state
+        .repo
+        .get_public_blocks(&claims.user_id, query.from, query.to)
+        .await;
 
     E1(Json(requests))
 }
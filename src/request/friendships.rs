@@ -5,62 +5,115 @@ use axum::{
     extract::{Query, State},
     response::IntoResponse,
 };
-use axum_extra::either::Either::{self, E1, E2};
+use axum_extra::either::{
+    Either::{self, E1, E2},
+    Either3,
+};
+use chrono::{Duration, Utc};
 use serde_json::to_vec;
 use topic_structs::{FriendRequestAnswered, FriendRequestCreated};
 
 use crate::{
     api_utils::{
-        responses,
+        responses::{
+            self, ApiResponse, ApiResponseConfusable, ApiResponseCooldown, ApiResponseMessage,
+        },
         structs::{
-            FriendRequestState, PublicFriendRequestReceived, PublicFriendRequestSent,
-            PublicFriendship, RequestFriendRequest, RequestFriendRequestRecieved,
-            RequestFriendRequestSent, RequestFriendships,
+            FriendRequestPolicy, FriendRequestState, OutboxEvent, PrivateUser,
+            PublicFriendRequestReceived, PublicFriendRequestSent, PublicFriendSuggestion,
+            PublicFriendship, PublicFriendshipPath, RequestFriendRequest,
+            RequestFriendRequestRecieved, RequestFriendRequestSent, RequestFriendSuggestions,
+            RequestFriendshipPath, RequestFriendships, RequestUserFriendships, Visibility,
         },
     },
     app::AppState,
+    events::{FriendRequestCancelled, FriendshipRemoved},
     jwt::Claims,
-    sql_utils::calls::{
-        get_private_block, get_private_friend_request, get_private_user,
-        get_public_friend_requests_received, get_public_friend_requests_sent,
-        get_public_friendships, get_public_user, insert_friend_request, insert_friendship,
-        update_friend_request_state,
-    },
+    policy::username::loose_username_skeleton,
+    social_path::{PathSearch, shortest_path},
 };
 
 pub async fn request_friend(
     State(state): State<Arc<AppState>>,
     claims: Claims,
     Json(body): Json<RequestFriendRequest>,
-) -> impl IntoResponse {
-    let Some(from_user) = get_public_user(&claims.user_id, &state.db).await else {
-        return responses::USER_DOES_NOT_EXIST;
+) -> Either3<ApiResponse<ApiResponseCooldown>, ApiResponse<ApiResponseConfusable>, impl IntoResponse>
+{
+    let Some(from_user) = state.repo.get_public_user(&claims.user_id).await else {
+        return Either3::E3(responses::USER_DOES_NOT_EXIST);
     };
 
-    let to_user = match get_private_user(&body.to_user_username, &state.db).await {
+    let to_user = match state.repo.get_private_user(&body.to_user_username).await {
         Some(e) => e,
-        None => return responses::USER_DOES_NOT_EXIST,
+        None => return Either3::E3(responses::USER_DOES_NOT_EXIST),
     };
 
-    if get_private_block(&to_user.id, &claims.user_id, &state.db)
+    if state
+        .repo
+        .get_private_block(&to_user.id, &claims.user_id)
         .await
         .is_some()
     {
-        return responses::USER_DOES_NOT_EXIST;
+        return Either3::E3(responses::USER_DOES_NOT_EXIST);
     }
 
-    if get_private_friend_request(&claims.user_id, &to_user.id, &state.db)
+    let reverse_pending = state
+        .repo
+        .get_private_friend_request(&to_user.id, &claims.user_id)
         .await
-        .is_some()
-    {
-        return responses::REQUEST_ALREADY_EXIST;
+        .is_some_and(|r| r.state == FriendRequestState::Pending.to_string());
+
+    if reverse_pending {
+        return Either3::E3(
+            accept_mutual_requests(&state, &claims.user_id, from_user.username, to_user).await,
+        );
     }
 
-    if insert_friend_request(&claims.user_id, &to_user.id, &state.db)
+    let settings = state
+        .repo
+        .get_settings(&to_user.id)
+        .await
+        .unwrap_or_default();
+
+    let allowed = match settings.friend_requests {
+        FriendRequestPolicy::Everyone => true,
+        FriendRequestPolicy::FriendsOfFriends => {
+            state
+                .repo
+                .has_mutual_friend(&claims.user_id, &to_user.id)
+                .await
+        }
+        FriendRequestPolicy::Nobody => false,
+    };
+
+    if !allowed {
+        return Either3::E3(responses::FRIEND_REQUESTS_NOT_ALLOWED);
+    }
+
+    // The cooldown belongs to the pair, whoever sent the last request and however it ended
+    if let Some(previous) = state
+        .repo
+        .get_latest_friend_request(&claims.user_id, &to_user.id)
         .await
-        .is_err()
     {
-        return responses::DB_ERROR;
+        match FriendRequestState::from(previous.state.as_str()) {
+            FriendRequestState::Pending | FriendRequestState::Accepted => {
+                return Either3::E3(responses::REQUEST_ALREADY_EXIST);
+            }
+            FriendRequestState::Rejected
+            | FriendRequestState::Removed
+            | FriendRequestState::Cancelled => {
+                let answered_at = previous.responded_at.unwrap_or_default();
+                let retry_after = (answered_at
+                    + Duration::seconds(state.friend_request_cooldown_secs)
+                    - Utc::now())
+                .num_seconds();
+
+                if retry_after > 0 {
+                    return Either3::E1(responses::request_cooldown(retry_after));
+                }
+            }
+        }
     }
 
     let request = FriendRequestCreated {
@@ -68,19 +121,83 @@ pub async fn request_friend(
     };
 
     let Ok(request_bytes) = to_vec(&request) else {
-        return responses::FLUVIO_ERROR;
+        return Either3::E3(responses::FLUVIO_ERROR);
+    };
+
+    let event = OutboxEvent {
+        topic: state.request_sent_topic.clone(),
+        key: to_user.id.clone(),
+        payload: request_bytes,
     };
 
     if state
-        .request_sent_producer
-        .send(to_user.id, request_bytes)
+        .repo
+        .insert_friend_request(&claims.user_id, &to_user.id, event)
         .await
         .is_err()
     {
-        return responses::FLUVIO_ERROR;
+        return Either3::E3(responses::DB_ERROR);
     }
 
-    responses::REQUEST_CREATED
+    match confusable_friend(&state, &claims.user_id, &to_user.username).await {
+        Some(friend) => Either3::E2(responses::request_created_confusable(friend)),
+        None => Either3::E3(responses::REQUEST_CREATED),
+    }
+}
+
+// A friend whose name only differs from `username` by lookalike characters or separators
+async fn confusable_friend(state: &AppState, user_id: &str, username: &str) -> Option<String> {
+    let skeleton = loose_username_skeleton(username);
+
+    state
+        .repo
+        .get_public_friendships(user_id, 0, i64::MAX)
+        .await?
+        .into_iter()
+        .map(|friend| friend.username)
+        .find(|friend| friend != username && loose_username_skeleton(friend) == skeleton)
+}
+
+// Both users want the friendship, answer both requests as accept_friend would
+async fn accept_mutual_requests(
+    state: &AppState,
+    from_user_id: &str,
+    from_username: String,
+    to_user: PrivateUser,
+) -> ApiResponse<ApiResponseMessage> {
+    let answers = [
+        (to_user.id.clone(), to_user.username),
+        (from_user_id.to_owned(), from_username),
+    ];
+
+    let mut events = Vec::with_capacity(answers.len());
+    for (key, from_username) in answers {
+        let answer = FriendRequestAnswered {
+            from_username,
+            accepted: true,
+        };
+
+        let Ok(answer_bytes) = to_vec(&answer) else {
+            return responses::FLUVIO_ERROR;
+        };
+
+        events.push(OutboxEvent {
+            topic: state.request_answered_topic.clone(),
+            key,
+            payload: answer_bytes,
+        });
+    }
+
+    if state
+        .repo
+        .accept_mutual_friend_requests(from_user_id, &to_user.id, events)
+        .await
+        .is_err()
+    {
+        return responses::DB_ERROR;
+    }
+
+    responses::FRIENDSHIP_CREATED
 }
 
 pub async fn accept_friend(
@@ -88,112 +205,209 @@ pub async fn accept_friend(
     claims: Claims,
     Json(body): Json<RequestFriendRequest>,
 ) -> impl IntoResponse {
-    let Some(_to_user) = get_public_user(&claims.user_id, &state.db).await else {
+    let Some(_to_user) = state.repo.get_public_user(&claims.user_id).await else {
         return responses::USER_DOES_NOT_EXIST;
     };
 
-    let from_user = match get_private_user(&body.to_user_username, &state.db).await {
+    let from_user = match state.repo.get_private_user(&body.to_user_username).await {
         Some(e) => e,
         None => return responses::USER_DOES_NOT_EXIST,
     };
 
-    let mut request =
-        match get_private_friend_request(&from_user.id, &claims.user_id, &state.db).await {
-            Some(e) => e,
-            None => return responses::REQUEST_DOES_NOT_EXIST,
-        };
+    let request = match state
+        .repo
+        .get_private_friend_request(&from_user.id, &claims.user_id)
+        .await
+    {
+        Some(e) => e,
+        None => return responses::REQUEST_DOES_NOT_EXIST,
+    };
 
     if request.state != FriendRequestState::Pending.to_string() {
         return responses::REQUEST_NOT_PENDING;
     }
 
-    request.state = FriendRequestState::Accepted.to_string();
-    if update_friend_request_state(request, &state.db)
+    let answer = FriendRequestAnswered {
+        from_username: from_user.username,
+        accepted: true,
+    };
+
+    let Ok(answer_bytes) = to_vec(&answer) else {
+        return responses::FLUVIO_ERROR;
+    };
+
+    let event = OutboxEvent {
+        topic: state.request_answered_topic.clone(),
+        key: from_user.id.clone(),
+        payload: answer_bytes,
+    };
+
+    if state
+        .repo
+        .accept_friend_request(&from_user.id, &claims.user_id, event)
         .await
         .is_err()
     {
         return responses::DB_ERROR;
     }
 
-    if insert_friendship(&from_user.id, &claims.user_id, &state.db)
+    responses::REQUEST_ACCEPTED
+}
+
+pub async fn reject_friend(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Json(body): Json<RequestFriendRequest>,
+) -> impl IntoResponse {
+    let Some(_to_user) = state.repo.get_public_user(&claims.user_id).await else {
+        return responses::USER_DOES_NOT_EXIST;
+    };
+
+    let from_user = match state.repo.get_private_user(&body.to_user_username).await {
+        Some(e) => e,
+        None => return responses::USER_DOES_NOT_EXIST,
+    };
+
+    let request = match state
+        .repo
+        .get_private_friend_request(&from_user.id, &claims.user_id)
         .await
-        .is_err()
     {
-        return responses::DB_ERROR;
+        Some(e) => e,
+        None => return responses::REQUEST_DOES_NOT_EXIST,
+    };
+
+    if request.state != FriendRequestState::Pending.to_string() {
+        return responses::REQUEST_NOT_PENDING;
     }
 
-    let request = FriendRequestAnswered {
+    let answer = FriendRequestAnswered {
         from_username: from_user.username,
-        accepted: true,
+        accepted: false,
     };
 
-    let Ok(request_bytes) = to_vec(&request) else {
+    let Ok(answer_bytes) = to_vec(&answer) else {
         return responses::FLUVIO_ERROR;
     };
 
+    let event = OutboxEvent {
+        topic: state.request_answered_topic.clone(),
+        key: from_user.id.clone(),
+        payload: answer_bytes,
+    };
+
     if state
-        .request_answered_producer
-        .send(from_user.id, request_bytes)
+        .repo
+        .reject_friend_request(&from_user.id, &claims.user_id, event)
         .await
         .is_err()
     {
-        return responses::FLUVIO_ERROR;
+        return responses::DB_ERROR;
     }
 
-    responses::REQUEST_ACCEPTED
+    responses::REQUEST_REJECTED
 }
 
-pub async fn reject_friend(
+pub async fn cancel_friend(
     State(state): State<Arc<AppState>>,
     claims: Claims,
     Json(body): Json<RequestFriendRequest>,
 ) -> impl IntoResponse {
-    let Some(_to_user) = get_public_user(&claims.user_id, &state.db).await else {
+    let Some(from_user) = state.repo.get_public_user(&claims.user_id).await else {
         return responses::USER_DOES_NOT_EXIST;
     };
 
-    let from_user = match get_private_user(&body.to_user_username, &state.db).await {
+    let to_user = match state.repo.get_private_user(&body.to_user_username).await {
         Some(e) => e,
         None => return responses::USER_DOES_NOT_EXIST,
     };
 
-    let mut request =
-        match get_private_friend_request(&from_user.id, &claims.user_id, &state.db).await {
-            Some(e) => e,
-            None => return responses::REQUEST_DOES_NOT_EXIST,
-        };
+    let request = match state
+        .repo
+        .get_private_friend_request(&claims.user_id, &to_user.id)
+        .await
+    {
+        Some(e) => e,
+        None => return responses::REQUEST_DOES_NOT_EXIST,
+    };
 
     if request.state != FriendRequestState::Pending.to_string() {
         return responses::REQUEST_NOT_PENDING;
     }
 
-    request.state = FriendRequestState::Rejected.to_string();
-    if update_friend_request_state(request, &state.db)
+    let cancelled = FriendRequestCancelled {
+        from_username: from_user.username,
+    };
+
+    let Ok(cancelled_bytes) = to_vec(&cancelled) else {
+        return responses::FLUVIO_ERROR;
+    };
+
+    let event = OutboxEvent {
+        topic: state.request_cancelled_topic.clone(),
+        key: to_user.id.clone(),
+        payload: cancelled_bytes,
+    };
+
+    if state
+        .repo
+        .cancel_friend_request(&claims.user_id, &to_user.id, event)
         .await
         .is_err()
     {
         return responses::DB_ERROR;
     }
 
-    let request = FriendRequestAnswered {
+    responses::REQUEST_CANCELLED
+}
+
+pub async fn remove_friend(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Json(body): Json<RequestFriendRequest>,
+) -> impl IntoResponse {
+    let Some(from_user) = state.repo.get_public_user(&claims.user_id).await else {
+        return responses::USER_DOES_NOT_EXIST;
+    };
+
+    let to_user = match state.repo.get_private_user(&body.to_user_username).await {
+        Some(e) => e,
+        None => return responses::USER_DOES_NOT_EXIST,
+    };
+
+    if state
+        .repo
+        .get_private_friendship(&claims.user_id, &to_user.id)
+        .await
+        .is_none()
+    {
+        return responses::FRIENDSHIP_DOES_NOT_EXIST;
+    }
+
+    let removed = FriendshipRemoved {
         from_username: from_user.username,
-        accepted: false,
     };
 
-    let Ok(request_bytes) = to_vec(&request) else {
+    let Ok(removed_bytes) = to_vec(&removed) else {
         return responses::FLUVIO_ERROR;
     };
 
+    let event = OutboxEvent {
+        topic: state.friendship_removed_topic.clone(),
+        key: to_user.id.clone(),
+        payload: removed_bytes,
+    };
+
     if state
-        .request_answered_producer
-        .send(from_user.id, request_bytes)
+        .repo
+        .delete_friendship(&claims.user_id, &to_user.id, event)
         .await
         .is_err()
     {
-        return responses::FLUVIO_ERROR;
+        return responses::DB_ERROR;
     }
 
-    responses::REQUEST_REJECTED
+    responses::FRIENDSHIP_REMOVED
 }
 
 pub async fn get_request_sent(
@@ -201,12 +415,14 @@ pub async fn get_request_sent(
     claims: Claims,
     Query(query): Query<RequestFriendRequestSent>,
 ) -> Either<Json<Option<Vec<PublicFriendRequestSent>>>, impl IntoResponse> {
-    if get_public_user(&claims.user_id, &state.db).await.is_none() {
+    if state.repo.get_public_user(&claims.user_id).await.is_none() {
         return E2(responses::USER_DOES_NOT_EXIST);
     }
 
-    let requests =
-        get_public_friend_requests_sent(&claims.user_id, query.from, query.to, &state.db).await;
+    let requests = state
+        .repo
+        .get_public_friend_requests_sent(&claims.user_id, query.from, query.to)
+        .await;
 
     E1(Json(requests))
 }
@@ -216,12 +432,14 @@ pub async fn get_request_received(
     claims: Claims,
     Query(query): Query<RequestFriendRequestRecieved>,
 ) -> Either<Json<Option<Vec<PublicFriendRequestReceived>>>, impl IntoResponse> {
-    if get_public_user(&claims.user_id, &state.db).await.is_none() {
+    if state.repo.get_public_user(&claims.user_id).await.is_none() {
         return E2(responses::USER_DOES_NOT_EXIST);
     }
 
-    let requests =
-        get_public_friend_requests_received(&claims.user_id, query.from, query.to, &state.db).await;
+    let requests = state
+        .repo
+        .get_public_friend_requests_received(&claims.user_id, query.from, query.to)
+        .await;
 
     E1(Json(requests))
 }
@@ -231,11 +449,175 @@ pub async fn get_friends(
     claims: Claims,
     Query(query): Query<RequestFriendships>,
 ) -> Either<Json<Option<Vec<PublicFriendship>>>, impl IntoResponse> {
-    if get_public_user(&claims.user_id, &state.db).await.is_none() {
+    if state.repo.get_public_user(&claims.user_id).await.is_none() {
         return E2(responses::USER_DOES_NOT_EXIST);
     }
 
-    let requests = get_public_friendships(&claims.user_id, query.from, query.to, &state.db).await;
+    let requests = state
+        .repo
+        .get_public_friendships(&claims.user_id, query.from, query.to)
+        .await;
 
     E1(Json(requests))
 }
+
+pub async fn get_friends_of(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Query(query): Query<RequestUserFriendships>,
+) -> Either<Json<Option<Vec<PublicFriendship>>>, impl IntoResponse> {
+    let target = match visible_user(&state, &claims.user_id, &query.username).await {
+        Ok(target) => target,
+        Err(e) => return E2(e),
+    };
+
+    if friends_hidden(&state, &claims.user_id, &target.id).await {
+        return E2(responses::FRIENDS_PRIVATE);
+    }
+
+    let friends = state
+        .repo
+        .get_public_friendships(&target.id, query.from, query.to)
+        .await;
+
+    E1(Json(friends))
+}
+
+pub async fn get_mutual_friends(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Query(query): Query<RequestUserFriendships>,
+) -> Either<Json<Option<Vec<PublicFriendship>>>, impl IntoResponse> {
+    let target = match visible_user(&state, &claims.user_id, &query.username).await {
+        Ok(target) => target,
+        Err(e) => return E2(e),
+    };
+
+    // Which of the caller's friends the target has is part of the target's friend list
+    if friends_hidden(&state, &claims.user_id, &target.id).await {
+        return E2(responses::FRIENDS_PRIVATE);
+    }
+
+    let friends = state
+        .repo
+        .get_public_mutual_friendships(&claims.user_id, &target.id, query.from, query.to)
+        .await;
+
+    E1(Json(friends))
+}
+
+pub async fn get_suggestions(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Query(query): Query<RequestFriendSuggestions>,
+) -> Either<Json<Option<Vec<PublicFriendSuggestion>>>, impl IntoResponse> {
+    if state.repo.get_public_user(&claims.user_id).await.is_none() {
+        return E2(responses::USER_DOES_NOT_EXIST);
+    }
+
+    let suggestions = state
+        .repo
+        .get_public_friend_suggestions(&claims.user_id, query.from, query.to)
+        .await;
+
+    E1(Json(suggestions))
+}
+
+pub async fn get_friendship_path(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Query(query): Query<RequestFriendshipPath>,
+) -> Either<Json<PublicFriendshipPath>, impl IntoResponse> {
+    let target = match visible_user(&state, &claims.user_id, &query.username).await {
+        Ok(target) => target,
+        Err(e) => return E2(e),
+    };
+
+    // The last step of the path would tell who the target is friends with
+    if friends_hidden(&state, &claims.user_id, &target.id).await {
+        return E2(responses::FRIENDS_PRIVATE);
+    }
+
+    let max_depth = query
+        .max_depth
+        .unwrap_or(state.friendship_path_max_depth)
+        .min(state.friendship_path_max_depth);
+
+    let ids = match shortest_path(
+        state.repo.as_ref(),
+        &claims.user_id,
+        &target.id,
+        max_depth,
+        state.friendship_path_max_friendships,
+    )
+    .await
+    {
+        PathSearch::Found(ids) => ids,
+        PathSearch::NotFound => return E2(responses::PATH_NOT_FOUND),
+        PathSearch::TooLarge => {
+            tracing::info!(
+                "Friendship path search from {} stopped after {} friendships",
+                claims.user_id,
+                state.friendship_path_max_friendships
+            );
+            return E2(responses::PATH_SEARCH_TOO_LARGE);
+        }
+    };
+
+    let mut path = Vec::with_capacity(ids.len());
+
+    for id in ids.iter() {
+        // Someone on the path was deleted in the meantime
+        let Some(user) = state.repo.get_public_user(id).await else {
+            return E2(responses::PATH_NOT_FOUND);
+        };
+        path.push(user.username);
+    }
+
+    E1(Json(PublicFriendshipPath {
+        degrees: path.len() - 1,
+        path,
+    }))
+}
+
+// Whether the friend list of `target_id` is hidden from `user_id`
+async fn friends_hidden(state: &AppState, user_id: &str, target_id: &str) -> bool {
+    if user_id == target_id {
+        return false;
+    }
+
+    let settings = state.repo.get_settings(target_id).await.unwrap_or_default();
+
+    settings.friends_visibility == Visibility::FriendsOnly
+        && state
+            .repo
+            .get_private_friendship(user_id, target_id)
+            .await
+            .is_none()
+}
+
+// The user named `username`, unless the caller is unknown or blocked by them
+async fn visible_user(
+    state: &AppState,
+    user_id: &str,
+    username: &str,
+) -> Result<PrivateUser, ApiResponse<ApiResponseMessage>> {
+    if state.repo.get_public_user(user_id).await.is_none() {
+        return Err(responses::USER_DOES_NOT_EXIST);
+    }
+
+    let Some(target) = state.repo.get_private_user(username).await else {
+        return Err(responses::USER_DOES_NOT_EXIST);
+    };
+
+    if state
+        .repo
+        .get_private_block(&target.id, user_id)
+        .await
+        .is_some()
+    {
+        return Err(responses::USER_DOES_NOT_EXIST);
+    }
+
+    Ok(target)
+}
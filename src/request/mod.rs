@@ -1,3 +1,4 @@
 pub(crate) mod block;
 pub(crate) mod friendships;
+pub(crate) mod settings;
 pub(crate) mod user;
@@ -0,0 +1,68 @@
+use std::sync::Arc;
+
+use axum::{Json, extract::State, response::IntoResponse};
+use axum_extra::either::Either::{self, E1, E2};
+
+use crate::{
+    api_utils::{
+        responses,
+        structs::{RequestUpdateSettings, UserSettings},
+    },
+    app::AppState,
+    jwt::Claims,
+};
+
+pub async fn get_settings(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+) -> Either<Json<UserSettings>, impl IntoResponse> {
+    if state.repo.get_public_user(&claims.user_id).await.is_none() {
+        return E2(responses::USER_DOES_NOT_EXIST);
+    }
+
+    let settings = state
+        .repo
+        .get_settings(&claims.user_id)
+        .await
+        .unwrap_or_default();
+
+    E1(Json(settings))
+}
+
+pub async fn update_settings(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Json(body): Json<RequestUpdateSettings>,
+) -> impl IntoResponse {
+    if state.repo.get_public_user(&claims.user_id).await.is_none() {
+        return responses::USER_DOES_NOT_EXIST;
+    }
+
+    let current = state
+        .repo
+        .get_settings(&claims.user_id)
+        .await
+        .unwrap_or_default();
+
+    // Missing fields keep their current value
+    let settings = UserSettings {
+        friend_requests: body.friend_requests.unwrap_or(current.friend_requests),
+        profile_visibility: body
+            .profile_visibility
+            .unwrap_or(current.profile_visibility),
+        friends_visibility: body
+            .friends_visibility
+            .unwrap_or(current.friends_visibility),
+    };
+
+    if state
+        .repo
+        .update_settings(&claims.user_id, settings)
+        .await
+        .is_err()
+    {
+        return responses::DB_ERROR;
+    }
+
+    responses::SETTINGS_UPDATED
+}
@@ -1,56 +1,250 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use axum::{
     Json,
     extract::{Query, State},
     response::IntoResponse,
 };
-use axum_extra::either::Either::{self, E1, E2};
+use axum_extra::either::{
+    Either::{self, E1, E2},
+    Either3,
+};
+use chrono::{Duration, Utc};
+use serde_json::to_vec;
 
 use crate::{
     api_utils::{
-        responses,
+        responses::{self, ApiResponse, ApiResponseCooldown, ApiResponseMessage},
         structs::{
-            PrivateUser, RequestUpdateProfile, RequestUpdateProfileEnum::Username,
-            RequestUserProfile,
+            FriendProfile, OutboxEvent, PrivateProfile, PublicProfile, RequestUpdateProfile,
+            RequestUpdateProfileEnum, RequestUserProfile, SelfProfile, Visibility,
         },
     },
     app::AppState,
+    events::{ProfileFieldChange, UserUpdated},
     jwt::Claims,
-    sql_utils::calls::{get_private_user, get_public_user, update_user_username},
+    policy::profile::validate_profile_field,
 };
 
 pub async fn update_profile(
     State(state): State<Arc<AppState>>,
     claims: Claims,
     Json(body): Json<RequestUpdateProfile>,
-) -> impl IntoResponse {
-    if get_public_user(&claims.user_id, &state.db).await.is_none() {
-        return responses::USER_DOES_NOT_EXIST;
-    }
+) -> Either<ApiResponse<ApiResponseCooldown>, ApiResponse<ApiResponseMessage>> {
+    let Some(user) = state.repo.get_public_user(&claims.user_id).await else {
+        return E2(responses::USER_DOES_NOT_EXIST);
+    };
+
+    let mut changes = HashMap::new();
 
-    for (part, value) in body.query.iter() {
-        let res = match part {
-            Username => update_user_username(&claims.user_id, value, &state.db).await,
+    for (part, value) in body.query {
+        match validate_profile_field(&part, &value, &state.reserved_usernames) {
+            Ok(value) => changes.insert(part, value),
+            Err(e) => return E2(e),
         };
+    }
+
+    let rename = match changes.get(&RequestUpdateProfileEnum::Username) {
+        Some(Some(username)) if *username != user.username => Some(username.clone()),
+        _ => None,
+    };
+
+    let checked = match rename {
+        Some(username) => check_rename(&state, &claims.user_id, &username).await,
+        None => Ok(()),
+    };
+
+    if let Err(e) = checked {
+        return e;
+    }
+
+    let Some(profile) = state.repo.get_private_profile(&user.username).await else {
+        return E2(responses::USER_DOES_NOT_EXIST);
+    };
+
+    let updated = UserUpdated {
+        user_id: claims.user_id.clone(),
+        changes: changes
+            .iter()
+            .filter_map(|(field, new)| {
+                let old = profile_field(&profile, field);
+                (old != *new).then(|| ProfileFieldChange {
+                    field: *field,
+                    old,
+                    new: new.clone(),
+                })
+            })
+            .collect(),
+    };
+
+    // Nothing would change, so there is nothing to tell other services either
+    if updated.changes.is_empty() {
+        return E2(responses::PROFILE_UPDATED);
+    }
+
+    let Ok(updated_bytes) = to_vec(&updated) else {
+        return E2(responses::FLUVIO_ERROR);
+    };
+
+    let event = OutboxEvent {
+        topic: state.user_updated_topic.clone(),
+        key: claims.user_id.clone(),
+        payload: updated_bytes,
+    };
+
+    if state
+        .repo
+        .update_profile(&claims.user_id, &changes, event)
+        .await
+        .is_err()
+    {
+        return E2(responses::DB_ERROR);
+    }
+
+    E2(responses::PROFILE_UPDATED)
+}
+
+fn profile_field(profile: &PrivateProfile, field: &RequestUpdateProfileEnum) -> Option<String> {
+    match field {
+        RequestUpdateProfileEnum::Username => Some(profile.username.clone()),
+        RequestUpdateProfileEnum::DisplayName => profile.display_name.clone(),
+        RequestUpdateProfileEnum::Bio => profile.bio.clone(),
+        RequestUpdateProfileEnum::Avatar => profile.avatar_url.clone(),
+        RequestUpdateProfileEnum::Banner => profile.banner_url.clone(),
+        RequestUpdateProfileEnum::Country => profile.country.clone(),
+    }
+}
 
-        match res {
-            Ok(_) => continue,
-            Err(_) => return responses::DB_ERROR,
+// Everything a new username needs besides the username policy itself
+async fn check_rename(
+    state: &AppState,
+    user_id: &str,
+    username: &str,
+) -> Result<(), Either<ApiResponse<ApiResponseCooldown>, ApiResponse<ApiResponseMessage>>> {
+    let now = Utc::now();
+
+    if let Some(changed_at) = state.repo.get_last_username_change(user_id).await {
+        let retry_after = (changed_at + Duration::seconds(state.username_change_cooldown_secs)
+            - now)
+            .num_seconds();
+
+        if retry_after > 0 {
+            return Err(E1(responses::username_change_cooldown(retry_after)));
         }
     }
 
-    responses::PROFILE_UPDATED
+    // Lookups ignore case, so this also catches names differing only by case
+    let taken = state
+        .repo
+        .get_private_user(username)
+        .await
+        .is_some_and(|user| user.id != user_id);
+
+    if taken {
+        return Err(E2(responses::USERNAME_TAKEN));
+    }
+
+    let confusable = state
+        .repo
+        .get_confusable_user(username)
+        .await
+        .is_some_and(|user| user.id != user_id);
+
+    if confusable {
+        return Err(E2(responses::USERNAME_CONFUSABLE));
+    }
+
+    // Only the previous owner can take a released name back during the hold
+    let held = state
+        .repo
+        .get_previous_username_owner(username, now - Duration::seconds(state.username_hold_secs))
+        .await
+        .is_some_and(|user| user.id != user_id);
+
+    if held {
+        return Err(E2(responses::USERNAME_ON_HOLD));
+    }
+
+    Ok(())
 }
 
 pub async fn get_user_info(
     State(state): State<Arc<AppState>>,
+    claims: Option<Claims>,
     Query(query): Query<RequestUserProfile>,
-) -> Either<Json<PrivateUser>, impl IntoResponse> {
-    let user = match get_private_user(&query.user_username, &state.db).await {
-        Some(e) => e,
-        None => return E2(responses::USER_DOES_NOT_EXIST),
+) -> Either3<Json<PublicProfile>, Json<FriendProfile>, impl IntoResponse> {
+    let Some(profile) = find_profile(&state, &query.user_username).await else {
+        return Either3::E3(responses::USER_DOES_NOT_EXIST);
+    };
+
+    let friendship = match claims {
+        Some(claims) if claims.user_id == profile.id => {
+            return Either3::E2(Json(FriendProfile::new(profile, None)));
+        }
+        Some(claims) => {
+            // Users who blocked the caller look as if they did not exist
+            if state
+                .repo
+                .get_private_block(&profile.id, &claims.user_id)
+                .await
+                .is_some()
+            {
+                return Either3::E3(responses::USER_DOES_NOT_EXIST);
+            }
+
+            state
+                .repo
+                .get_private_friendship(&claims.user_id, &profile.id)
+                .await
+        }
+        None => None,
     };
 
-    E1(Json(user))
+    if let Some(friendship) = friendship {
+        return Either3::E2(Json(FriendProfile::new(profile, friendship.created_at)));
+    }
+
+    let settings = state
+        .repo
+        .get_settings(&profile.id)
+        .await
+        .unwrap_or_default();
+
+    if settings.profile_visibility == Visibility::FriendsOnly {
+        return Either3::E3(responses::PROFILE_PRIVATE);
+    }
+
+    Either3::E1(Json(profile.into()))
+}
+
+pub async fn get_self_info(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+) -> Either<Json<SelfProfile>, impl IntoResponse> {
+    let Some(user) = state.repo.get_public_user(&claims.user_id).await else {
+        return E2(responses::USER_DOES_NOT_EXIST);
+    };
+
+    let Some(profile) = state.repo.get_private_profile(&user.username).await else {
+        return E2(responses::USER_DOES_NOT_EXIST);
+    };
+
+    let changed_at = state.repo.get_last_username_change(&claims.user_id).await;
+
+    E1(Json(SelfProfile::new(profile, changed_at)))
+}
+
+async fn find_profile(state: &AppState, username: &str) -> Option<PrivateProfile> {
+    if let Some(profile) = state.repo.get_private_profile(username).await {
+        return Some(profile);
+    }
+
+    // Old names keep pointing to their owner while they are on hold
+    let since = Utc::now() - Duration::seconds(state.username_hold_secs);
+    let owner = state
+        .repo
+        .get_previous_username_owner(username, since)
+        .await?;
+
+    state.repo.get_private_profile(&owner.username).await
 }
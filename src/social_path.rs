@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+use crate::repository::Repository;
+
+pub enum PathSearch {
+    /// User ids from the viewer to the target, both included
+    Found(Vec<String>),
+    NotFound,
+    /// More friendships than allowed had to be read before finding a path
+    TooLarge,
+}
+
+// One side of the bidirectional search, `parents` maps every reached user to the one
+// it was reached from
+struct Side {
+    parents: HashMap<String, Option<String>>,
+    frontier: Vec<String>,
+}
+
+impl Side {
+    fn new(start: &str) -> Self {
+        Self {
+            parents: HashMap::from([(start.to_owned(), None)]),
+            frontier: vec![start.to_owned()],
+        }
+    }
+
+    // From `id` back to where the side started
+    fn walk(&self, id: &str) -> Vec<String> {
+        let mut path = vec![id.to_owned()];
+
+        while let Some(Some(parent)) = self.parents.get(path.last().unwrap()) {
+            path.push(parent.clone());
+        }
+
+        path
+    }
+}
+
+/// Shortest friendship path between `viewer_id` and `target_id` of at most `max_depth`
+/// friendships, only going through users whose friends the viewer may see. Reads at most
+/// `max_friendships` friendships before giving up
+pub async fn shortest_path(
+    repo: &dyn Repository,
+    viewer_id: &str,
+    target_id: &str,
+    max_depth: usize,
+    max_friendships: i64,
+) -> PathSearch {
+    if viewer_id == target_id {
+        return PathSearch::Found(vec![viewer_id.to_owned()]);
+    }
+
+    let mut forward = Side::new(viewer_id);
+    let mut backward = Side::new(target_id);
+    let mut read = 0;
+
+    for _ in 0..max_depth {
+        // Expanding the smaller side keeps both searches about as wide
+        let forward_turn = forward.frontier.len() <= backward.frontier.len();
+        let (side, other) = if forward_turn {
+            (&mut forward, &backward)
+        } else {
+            (&mut backward, &forward)
+        };
+
+        if side.frontier.is_empty() {
+            return PathSearch::NotFound;
+        }
+
+        let limit = max_friendships - read;
+        let Some(friendships) = repo
+            .get_traversable_friendships(viewer_id, &side.frontier, limit)
+            .await
+        else {
+            return PathSearch::NotFound;
+        };
+
+        // A full page means there may be more, the search would go over its budget
+        if friendships.len() as i64 >= limit {
+            return PathSearch::TooLarge;
+        }
+        read += friendships.len() as i64;
+
+        let mut next = Vec::new();
+        let mut meeting = None;
+
+        for friendship in friendships {
+            if side.parents.contains_key(&friendship.from_user_id) {
+                continue;
+            }
+
+            side.parents
+                .insert(friendship.from_user_id.clone(), Some(friendship.to_user_id));
+
+            if other.parents.contains_key(&friendship.from_user_id) {
+                meeting = Some(friendship.from_user_id);
+                break;
+            }
+
+            next.push(friendship.from_user_id);
+        }
+
+        side.frontier = next;
+
+        if let Some(meeting) = meeting {
+            let mut path = forward.walk(&meeting);
+            path.reverse();
+            path.extend(backward.walk(&meeting).into_iter().skip(1));
+
+            return PathSearch::Found(path);
+        }
+    }
+
+    PathSearch::NotFound
+}
@@ -1,11 +1,16 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+
 use crate::api_utils::structs::{
-    PrivateBlocked, PrivateFriendRequest, PrivateFriendship, PrivateUser, PublicBlocked,
-    PublicFriendRequestReceived, PublicFriendRequestSent, PublicFriendship, PublicUser,
+    ConsumerOffset, PrivateBlocked, PrivateFriendRequest, PrivateFriendship, PrivateOutboxEvent,
+    PrivateProfile, PrivateSettings, PrivateUser, PublicBlocked, PublicFriendRequestReceived,
+    PublicFriendRequestSent, PublicFriendSuggestion, PublicFriendship, PublicUser,
+    RequestUpdateProfileEnum, UserSettings,
 };
 
 //--------------------GETTERS--------------------
 
-pub async fn get_public_user(id: &str, db: &sqlx::PgPool) -> Option<PublicUser> {
+pub async fn get_public_user(id: &str, db: impl PgExecutor<'_>) -> Option<PublicUser> {
     sqlx::query_as(
         "
         SELECT username, created_at 
@@ -19,24 +24,204 @@ pub async fn get_public_user(id: &str, db: &sqlx::PgPool) -> Option<PublicUser>
     .ok()
 }
 
-pub async fn get_private_user(username: &str, db: &sqlx::PgPool) -> Option<PrivateUser> {
+pub async fn find_public_user(
+    id: &str,
+    db: impl PgExecutor<'_>,
+) -> anyhow::Result<Option<PublicUser>> {
+    let user = sqlx::query_as(
+        "
+        SELECT username, created_at
+        FROM users
+        WHERE id = $1
+    ",
+    )
+    .bind(id)
+    .fetch_optional(db)
+    .await?;
+
+    Ok(user)
+}
+
+pub async fn get_user_purged(id: &str, db: impl PgExecutor<'_>) -> anyhow::Result<bool> {
+    let purged = sqlx::query_scalar(
+        "
+        SELECT EXISTS (SELECT 1 FROM purged_users WHERE user_id = $1)
+    ",
+    )
+    .bind(id)
+    .fetch_one(db)
+    .await?;
+
+    Ok(purged)
+}
+
+pub async fn get_private_user(username: &str, db: impl PgExecutor<'_>) -> Option<PrivateUser> {
     sqlx::query_as(
         "
         SELECT id, username, created_at 
         FROM users 
-        WHERE username = $1
+        WHERE lower(username) = lower($1)
+    ",
+    )
+    .bind(username)
+    .fetch_one(db)
+    .await
+    .ok()
+}
+
+pub async fn get_private_profile(
+    username: &str,
+    db: impl PgExecutor<'_>,
+) -> Option<PrivateProfile> {
+    sqlx::query_as(
+        "
+        SELECT id, username, display_name, bio, avatar_url, banner_url, country, created_at
+        FROM users
+        WHERE lower(username) = lower($1)
+    ",
+    )
+    .bind(username)
+    .fetch_one(db)
+    .await
+    .ok()
+}
+
+pub async fn get_user_by_skeleton(skeleton: &str, db: impl PgExecutor<'_>) -> Option<PrivateUser> {
+    sqlx::query_as(
+        "
+        SELECT id, username, created_at
+        FROM users
+        WHERE username_skeleton = $1
+    ",
+    )
+    .bind(skeleton)
+    .fetch_one(db)
+    .await
+    .ok()
+}
+
+pub async fn get_users_without_skeleton(
+    db: impl PgExecutor<'_>,
+) -> anyhow::Result<Vec<PrivateUser>> {
+    let users = sqlx::query_as(
+        "
+        SELECT id, username, created_at
+        FROM users
+        WHERE username_skeleton IS NULL
+    ",
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(users)
+}
+
+// Users sharing their lowercased username with someone else, oldest first in every group
+pub async fn get_case_duplicate_users(
+    db: impl PgExecutor<'_>,
+) -> anyhow::Result<Vec<(String, String, String)>> {
+    let users = sqlx::query_as(
+        "
+        SELECT id, username, lower(username)
+        FROM users
+        WHERE lower(username) IN (
+        SELECT lower(username)
+        FROM users
+        GROUP BY lower(username)
+        HAVING COUNT(*) > 1
+        )
+        ORDER BY lower(username), created_at, id
+    ",
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(users)
+}
+
+pub async fn get_last_username_change(
+    user_id: &str,
+    db: impl PgExecutor<'_>,
+) -> Option<DateTime<Utc>> {
+    sqlx::query_scalar(
+        "
+        SELECT released_at
+        FROM username_history
+        WHERE user_id = $1
+        ORDER BY released_at DESC
+        LIMIT 1
+    ",
+    )
+    .bind(user_id)
+    .fetch_one(db)
+    .await
+    .ok()
+}
+
+pub async fn get_previous_username_owner(
+    username: &str,
+    since: DateTime<Utc>,
+    db: impl PgExecutor<'_>,
+) -> Option<PrivateUser> {
+    sqlx::query_as(
+        "
+        SELECT u.id, u.username, u.created_at
+        FROM username_history h
+        JOIN users u
+        ON u.id = h.user_id
+        WHERE lower(h.username) = lower($1) AND h.released_at > $2
+        ORDER BY h.released_at DESC
+        LIMIT 1
     ",
     )
     .bind(username)
+    .bind(since)
     .fetch_one(db)
     .await
     .ok()
 }
 
+pub async fn get_private_settings(
+    user_id: &str,
+    db: impl PgExecutor<'_>,
+) -> Option<PrivateSettings> {
+    sqlx::query_as(
+        "
+        SELECT friend_requests, profile_visibility, friends_visibility
+        FROM user_settings
+        WHERE user_id = $1
+    ",
+    )
+    .bind(user_id)
+    .fetch_one(db)
+    .await
+    .ok()
+}
+
+pub async fn get_consumer_offset(
+    consumer: &str,
+    topic: &str,
+    db: impl PgExecutor<'_>,
+) -> anyhow::Result<Option<i64>> {
+    let offset = sqlx::query_scalar(
+        "
+        SELECT next_offset
+        FROM consumer_offsets
+        WHERE consumer = $1 AND topic = $2
+    ",
+    )
+    .bind(consumer)
+    .bind(topic)
+    .fetch_optional(db)
+    .await?;
+
+    Ok(offset)
+}
+
 pub async fn get_private_friendship(
     from_user_id: &str,
     to_user_id: &str,
-    db: &sqlx::PgPool,
+    db: impl PgExecutor<'_>,
 ) -> Option<PrivateFriendship> {
     sqlx::query_as(
         "
@@ -52,11 +237,162 @@ pub async fn get_private_friendship(
     .ok()
 }
 
+pub async fn get_public_mutual_friendships(
+    user_id: &str,
+    other_user_id: &str,
+    from: i64,
+    to: i64,
+    db: impl PgExecutor<'_>,
+) -> Option<Vec<PublicFriendship>> {
+    if from > to {
+        return None;
+    }
+
+    let limit = (to - from).max(1);
+
+    sqlx::query_as(
+        "
+        SELECT u.username, mine.created_at
+        FROM friendships mine
+        JOIN friendships theirs
+        ON theirs.from_user_id = mine.from_user_id
+        JOIN users u
+        ON u.id = mine.from_user_id
+        WHERE mine.to_user_id = $1 AND theirs.to_user_id = $2
+        ORDER BY mine.created_at
+        LIMIT $3 OFFSET $4
+    ",
+    )
+    .bind(user_id)
+    .bind(other_user_id)
+    .bind(limit)
+    .bind(from)
+    .fetch_all(db)
+    .await
+    .ok()
+}
+
+pub async fn get_public_friend_suggestions(
+    user_id: &str,
+    from: i64,
+    to: i64,
+    db: impl PgExecutor<'_>,
+) -> Option<Vec<PublicFriendSuggestion>> {
+    if from > to {
+        return None;
+    }
+
+    let limit = (to - from).max(1);
+
+    sqlx::query_as(
+        "
+        SELECT u.username, COUNT(*) AS mutual_friends
+        FROM friendships mine
+        JOIN friendships theirs
+        ON theirs.from_user_id = mine.from_user_id
+        JOIN users u
+        ON u.id = theirs.to_user_id
+        LEFT JOIN user_settings s
+        ON s.user_id = u.id
+        WHERE mine.to_user_id = $1
+        AND u.id <> $1
+        AND COALESCE(s.friend_requests, 'everyone') <> 'nobody'
+        AND NOT EXISTS (
+        SELECT 1 FROM friendships f
+        WHERE f.from_user_id = $1 AND f.to_user_id = u.id
+        )
+        AND NOT EXISTS (
+        SELECT 1 FROM blocks b
+        WHERE (b.from_user_id = $1 AND b.to_user_id = u.id)
+        OR (b.from_user_id = u.id AND b.to_user_id = $1)
+        )
+        AND NOT EXISTS (
+        SELECT 1 FROM friend_requests r
+        WHERE r.state = 'pending'
+        AND ((r.from_user_id = $1 AND r.to_user_id = u.id)
+        OR (r.from_user_id = u.id AND r.to_user_id = $1))
+        )
+        GROUP BY u.id, u.username
+        ORDER BY mutual_friends DESC, u.username
+        LIMIT $2 OFFSET $3
+    ",
+    )
+    .bind(user_id)
+    .bind(limit)
+    .bind(from)
+    .fetch_all(db)
+    .await
+    .ok()
+}
+
+pub async fn get_traversable_friendships(
+    viewer_id: &str,
+    user_ids: &[String],
+    limit: i64,
+    db: impl PgExecutor<'_>,
+) -> Option<Vec<PrivateFriendship>> {
+    sqlx::query_as(
+        "
+        SELECT f.from_user_id, f.to_user_id, f.created_at
+        FROM friendships f
+        LEFT JOIN user_settings s
+        ON s.user_id = f.from_user_id
+        WHERE f.to_user_id = ANY($2)
+        AND (
+        f.from_user_id = $1
+        OR (
+        (
+        COALESCE(s.friends_visibility, 'public') = 'public'
+        OR EXISTS (
+        SELECT 1 FROM friendships v
+        WHERE v.from_user_id = $1 AND v.to_user_id = f.from_user_id
+        )
+        )
+        AND NOT EXISTS (
+        SELECT 1 FROM blocks b
+        WHERE (b.from_user_id = $1 AND b.to_user_id = f.from_user_id)
+        OR (b.from_user_id = f.from_user_id AND b.to_user_id = $1)
+        )
+        )
+        )
+        LIMIT $3
+    ",
+    )
+    .bind(viewer_id)
+    .bind(user_ids)
+    .bind(limit)
+    .fetch_all(db)
+    .await
+    .ok()
+}
+
+pub async fn get_mutual_friend_exists(
+    user_id: &str,
+    other_user_id: &str,
+    db: impl PgExecutor<'_>,
+) -> bool {
+    sqlx::query_scalar(
+        "
+        SELECT EXISTS (
+        SELECT 1
+        FROM friendships f1
+        JOIN friendships f2 ON f2.from_user_id = f1.to_user_id
+        WHERE f1.from_user_id = $1 AND f2.to_user_id = $2
+        )
+    ",
+    )
+    .bind(user_id)
+    .bind(other_user_id)
+    .fetch_one(db)
+    .await
+    .unwrap_or(false)
+}
+
 pub async fn get_public_friend_requests_received(
     to_user_id: &str,
     from: i64,
     to: i64,
-    db: &sqlx::PgPool,
+    db: impl PgExecutor<'_>,
 ) -> Option<Vec<PublicFriendRequestReceived>> {
     if from > to {
         return None;
@@ -67,10 +403,20 @@ pub async fn get_public_friend_requests_received(
     sqlx::query_as(
         "
         SELECT u.username, fr.state, fr.created_at 
-        FROM friend_requests fr
+        FROM (
+            SELECT DISTINCT ON (from_user_id) from_user_id, state, created_at
+            FROM friend_requests
+            WHERE to_user_id = $1
+            ORDER BY from_user_id, id DESC
+        ) fr
         JOIN users u
         ON u.id = fr.from_user_id 
-        WHERE fr.to_user_id = $1
+        WHERE fr.state <> 'cancelled'
+        AND NOT EXISTS (
+        SELECT 1 FROM blocks b
+        WHERE (b.from_user_id = $1 AND b.to_user_id = u.id)
+        OR (b.from_user_id = u.id AND b.to_user_id = $1)
+        )
         ORDER BY fr.created_at
         LIMIT $2 OFFSET $3
     ",
@@ -87,7 +433,7 @@ pub async fn get_public_friend_requests_sent(
     from_user_id: &str,
     from: i64,
     to: i64,
-    db: &sqlx::PgPool,
+    db: impl PgExecutor<'_>,
 ) -> Option<Vec<PublicFriendRequestSent>> {
     if from > to {
         return None;
@@ -98,10 +444,20 @@ pub async fn get_public_friend_requests_sent(
     sqlx::query_as(
         "
         SELECT u.username, fr.state, fr.created_at 
-        FROM friend_requests fr
+        FROM (
+            SELECT DISTINCT ON (to_user_id) to_user_id, state, created_at
+            FROM friend_requests
+            WHERE from_user_id = $1
+            ORDER BY to_user_id, id DESC
+        ) fr
         JOIN users u
         ON u.id = fr.to_user_id 
-        WHERE fr.from_user_id = $1
+        WHERE fr.state <> 'cancelled'
+        AND NOT EXISTS (
+        SELECT 1 FROM blocks b
+        WHERE (b.from_user_id = $1 AND b.to_user_id = u.id)
+        OR (b.from_user_id = u.id AND b.to_user_id = $1)
+        )
         ORDER BY fr.created_at
         LIMIT $2 OFFSET $3
     ",
@@ -118,7 +474,7 @@ pub async fn get_public_friendships(
     from_user_id: &str,
     from: i64,
     to: i64,
-    db: &sqlx::PgPool,
+    db: impl PgExecutor<'_>,
 ) -> Option<Vec<PublicFriendship>> {
     if from > to {
         return None;
@@ -148,7 +504,7 @@ pub async fn get_public_friendships(
 pub async fn get_private_block(
     from_user_id: &str,
     to_user_id: &str,
-    db: &sqlx::PgPool,
+    db: impl PgExecutor<'_>,
 ) -> Option<PrivateBlocked> {
     sqlx::query_as(
         "
@@ -168,7 +524,7 @@ pub async fn get_public_blocks(
     from_user_id: &str,
     from: i64,
     to: i64,
-    db: &sqlx::PgPool,
+    db: impl PgExecutor<'_>,
 ) -> Option<Vec<PublicBlocked>> {
     sqlx::query_as(
         "
@@ -192,13 +548,15 @@ pub async fn get_public_blocks(
 pub async fn get_private_friend_request(
     from_user_id: &str,
     to_user_id: &str,
-    db: &sqlx::PgPool,
+    db: impl PgExecutor<'_>,
 ) -> Option<PrivateFriendRequest> {
     sqlx::query_as(
         "
-            SELECT from_user_id, to_user_id, state, created_at
+            SELECT from_user_id, to_user_id, state, created_at, responded_at
             FROM friend_requests
             WHERE from_user_id = $1 AND to_user_id = $2
+            ORDER BY id DESC
+            LIMIT 1
         ",
     )
     .bind(from_user_id)
@@ -208,42 +566,97 @@ pub async fn get_private_friend_request(
     .ok()
 }
 
-pub async fn get_undirected_private_friend_requests(
-    from_user_id: &str,
-    to_user_id: &str,
-    db: &sqlx::PgPool,
-) -> Option<Vec<PrivateFriendRequest>> {
+pub async fn get_latest_undirected_private_friend_request(
+    a_user_id: &str,
+    b_user_id: &str,
+    db: impl PgExecutor<'_>,
+) -> Option<PrivateFriendRequest> {
     sqlx::query_as(
         "
-            SELECT from_user_id, to_user_id, state, created_at
+            SELECT from_user_id, to_user_id, state, created_at, responded_at
             FROM friend_requests
             WHERE (from_user_id = $1 AND to_user_id = $2) OR (from_user_id = $2 AND to_user_id = $1)
+            ORDER BY id DESC
+            LIMIT 1
         ",
     )
-    .bind(from_user_id)
-    .bind(to_user_id)
-    .fetch_all(db)
+    .bind(a_user_id)
+    .bind(b_user_id)
+    .fetch_one(db)
     .await
     .ok()
 }
 
+// Locks the row so the request can't be answered twice concurrently
+pub async fn get_pending_friend_request_for_update(
+    from_user_id: &str,
+    to_user_id: &str,
+    db: impl PgExecutor<'_>,
+) -> anyhow::Result<Option<PrivateFriendRequest>> {
+    let request = sqlx::query_as(
+        "
+            SELECT from_user_id, to_user_id, state, created_at, responded_at
+            FROM friend_requests
+            WHERE from_user_id = $1 AND to_user_id = $2 AND state = 'pending'
+            FOR UPDATE
+        ",
+    )
+    .bind(from_user_id)
+    .bind(to_user_id)
+    .fetch_optional(db)
+    .await?;
+
+    Ok(request)
+}
+
 //--------------------INSERTS--------------------
 
-pub async fn insert_user(user: PrivateUser, db: &sqlx::PgPool) -> anyhow::Result<()> {
+pub async fn insert_user(
+    user: PrivateUser,
+    skeleton: &str,
+    db: impl PgExecutor<'_>,
+) -> anyhow::Result<()> {
     sqlx::query(
         "
-            INSERT INTO users (id, username) VALUES ($1, $2)
+            INSERT INTO users (id, username, username_skeleton) VALUES ($1, $2, $3)
         ",
     )
     .bind(user.id)
     .bind(user.username)
+    .bind(skeleton)
     .execute(db)
     .await?;
 
     Ok(())
 }
 
-pub async fn insert_friend_request(from: &str, to: &str, db: &sqlx::PgPool) -> anyhow::Result<()> {
+// Records the current name as released, unless `new_username` is that same name
+pub async fn insert_username_history(
+    user_id: &str,
+    new_username: &str,
+    db: impl PgExecutor<'_>,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        "
+        INSERT INTO username_history (user_id, username)
+        SELECT id, username
+        FROM users
+        WHERE id = $1 AND username <> $2
+    ",
+    )
+    .bind(user_id)
+    .bind(new_username)
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn insert_friend_request(
+    from: &str,
+    to: &str,
+    db: impl PgExecutor<'_>,
+) -> anyhow::Result<()> {
     sqlx::query(
         "
         INSERT 
@@ -262,7 +675,7 @@ pub async fn insert_friend_request(from: &str, to: &str, db: &sqlx::PgPool) -> a
 pub async fn insert_friendship(
     a_user_id: &str,
     b_user_id: &str,
-    db: &sqlx::PgPool,
+    db: impl PgExecutor<'_>,
 ) -> anyhow::Result<()> {
     sqlx::query(
         "
@@ -279,7 +692,7 @@ pub async fn insert_friendship(
     Ok(())
 }
 
-pub async fn insert_block(block: PrivateBlocked, db: &sqlx::PgPool) -> anyhow::Result<()> {
+pub async fn insert_block(block: PrivateBlocked, db: impl PgExecutor<'_>) -> anyhow::Result<()> {
     sqlx::query(
         "
         INSERT
@@ -295,21 +708,55 @@ pub async fn insert_block(block: PrivateBlocked, db: &sqlx::PgPool) -> anyhow::R
     Ok(())
 }
 
+pub async fn insert_outbox_event(
+    topic: &str,
+    key: &str,
+    payload: Vec<u8>,
+    db: impl PgExecutor<'_>,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        "
+        INSERT
+        INTO outbox (topic, key, payload)
+        VALUES ($1, $2, $3)
+    ",
+    )
+    .bind(topic)
+    .bind(key)
+    .bind(payload)
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
 //--------------------DELETE--------------------
 
-pub async fn delete_friend_request(
-    request: PrivateFriendRequest,
-    db: &sqlx::PgPool,
-) -> anyhow::Result<()> {
+// Friendships, requests, blocks and username history go with it through ON DELETE CASCADE
+pub async fn insert_purged_user(id: &str, db: impl PgExecutor<'_>) -> anyhow::Result<()> {
     sqlx::query(
         "
-        DELETE 
-        FROM friend_requests 
-        WHERE (from_user_id = $1 AND to_user_id = $2) OR (from_user_id = $2 AND to_user_id = $1)
+        INSERT INTO purged_users (user_id)
+        VALUES ($1)
+        ON CONFLICT (user_id) DO NOTHING
     ",
     )
-    .bind(request.from_user_id)
-    .bind(request.to_user_id)
+    .bind(id)
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn delete_user(id: &str, db: impl PgExecutor<'_>) -> anyhow::Result<()> {
+    sqlx::query(
+        "
+        DELETE
+        FROM users
+        WHERE id = $1
+    ",
+    )
+    .bind(id)
     .execute(db)
     .await?;
 
@@ -318,7 +765,7 @@ pub async fn delete_friend_request(
 
 pub async fn delete_friendship(
     friendship: PrivateFriendship,
-    db: &sqlx::PgPool,
+    db: impl PgExecutor<'_>,
 ) -> anyhow::Result<()> {
     sqlx::query(
         "
@@ -335,7 +782,7 @@ pub async fn delete_friendship(
     Ok(())
 }
 
-pub async fn delete_block(block: PrivateBlocked, db: &sqlx::PgPool) -> anyhow::Result<()> {
+pub async fn delete_block(block: PrivateBlocked, db: impl PgExecutor<'_>) -> anyhow::Result<()> {
     sqlx::query(
         "
         DELETE 
@@ -353,20 +800,99 @@ pub async fn delete_block(block: PrivateBlocked, db: &sqlx::PgPool) -> anyhow::R
 
 //--------------------UPDATE--------------------
 
-pub async fn update_user_username(
+pub async fn update_consumer_offset(
+    offset: ConsumerOffset,
+    db: impl PgExecutor<'_>,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        "
+        INSERT INTO consumer_offsets (consumer, topic, next_offset)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (consumer, topic)
+        DO UPDATE SET next_offset = EXCLUDED.next_offset, updated_at = CURRENT_TIMESTAMP
+    ",
+    )
+    .bind(offset.consumer)
+    .bind(offset.topic)
+    .bind(offset.offset)
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn update_user_settings(
+    user_id: &str,
+    settings: &UserSettings,
+    db: impl PgExecutor<'_>,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        "
+        INSERT INTO user_settings (user_id, friend_requests, profile_visibility, friends_visibility)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (user_id)
+        DO UPDATE SET
+        friend_requests = EXCLUDED.friend_requests,
+        profile_visibility = EXCLUDED.profile_visibility,
+        friends_visibility = EXCLUDED.friends_visibility,
+        updated_at = CURRENT_TIMESTAMP
+    ",
+    )
+    .bind(user_id)
+    .bind(settings.friend_requests.to_string())
+    .bind(settings.profile_visibility.to_string())
+    .bind(settings.friends_visibility.to_string())
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn update_user_username_skeleton(
     id: &str,
-    username: &str,
-    db: &sqlx::PgPool,
+    skeleton: &str,
+    db: impl PgExecutor<'_>,
 ) -> anyhow::Result<()> {
     sqlx::query(
         "
         UPDATE users
-        SET username = $2
+        SET username_skeleton = $2
         WHERE id = $1
     ",
     )
     .bind(id)
-    .bind(username)
+    .bind(skeleton)
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn update_user_profile_field(
+    id: &str,
+    field: &RequestUpdateProfileEnum,
+    value: Option<&str>,
+    db: impl PgExecutor<'_>,
+) -> anyhow::Result<()> {
+    let column = match field {
+        RequestUpdateProfileEnum::Username => "username",
+        RequestUpdateProfileEnum::DisplayName => "display_name",
+        RequestUpdateProfileEnum::Bio => "bio",
+        RequestUpdateProfileEnum::Avatar => "avatar_url",
+        RequestUpdateProfileEnum::Banner => "banner_url",
+        RequestUpdateProfileEnum::Country => "country",
+    };
+
+    // The column comes from the match above, never from user input
+    sqlx::query(&format!(
+        "
+        UPDATE users
+        SET {column} = $2
+        WHERE id = $1
+    "
+    ))
+    .bind(id)
+    .bind(value)
     .execute(db)
     .await?;
 
@@ -375,18 +901,150 @@ pub async fn update_user_username(
 
 pub async fn update_friend_request_state(
     friend_request: PrivateFriendRequest,
-    db: &sqlx::PgPool,
+    db: impl PgExecutor<'_>,
 ) -> anyhow::Result<()> {
-    sqlx::query(
+    // Only the pending request can be answered, older ones are history
+    let result = sqlx::query(
         "
         UPDATE friend_requests
         SET state = $1, responded_at = CURRENT_TIMESTAMP
-        WHERE from_user_id = $2 AND to_user_id = $3
+        WHERE from_user_id = $2 AND to_user_id = $3 AND state = 'pending'
     ",
     )
     .bind(friend_request.state.to_string())
-    .bind(friend_request.from_user_id)
-    .bind(friend_request.to_user_id)
+    .bind(&friend_request.from_user_id)
+    .bind(&friend_request.to_user_id)
+    .execute(db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        anyhow::bail!(
+            "No pending request from {} to {}",
+            friend_request.from_user_id,
+            friend_request.to_user_id
+        );
+    }
+
+    Ok(())
+}
+
+pub async fn update_friend_requests_cancelled(
+    a_user_id: &str,
+    b_user_id: &str,
+    db: impl PgExecutor<'_>,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        "
+        UPDATE friend_requests
+        SET state = 'cancelled', responded_at = CURRENT_TIMESTAMP
+        WHERE ((from_user_id = $1 AND to_user_id = $2) OR (from_user_id = $2 AND to_user_id = $1))
+        AND state = 'pending'
+    ",
+    )
+    .bind(a_user_id)
+    .bind(b_user_id)
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn update_friend_requests_removed(
+    a_user_id: &str,
+    b_user_id: &str,
+    db: impl PgExecutor<'_>,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        "
+        UPDATE friend_requests
+        SET state = 'removed', responded_at = CURRENT_TIMESTAMP
+        WHERE ((from_user_id = $1 AND to_user_id = $2) OR (from_user_id = $2 AND to_user_id = $1))
+        AND state = 'accepted'
+    ",
+    )
+    .bind(a_user_id)
+    .bind(b_user_id)
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+// Leases due events so other relays skip them while they are published outside of any
+// transaction. Events queued after an unpublished event with the same key wait for it
+pub async fn update_outbox_events_claimed(
+    limit: i64,
+    lease_secs: i64,
+    db: impl PgExecutor<'_>,
+) -> anyhow::Result<Vec<PrivateOutboxEvent>> {
+    let mut events: Vec<PrivateOutboxEvent> = sqlx::query_as(
+        "
+        UPDATE outbox
+        SET next_attempt_at = CURRENT_TIMESTAMP + make_interval(secs => $2)
+        WHERE id IN (
+        SELECT o.id
+        FROM outbox o
+        WHERE o.published_at IS NULL
+        AND o.next_attempt_at <= CURRENT_TIMESTAMP
+        AND NOT EXISTS (
+        SELECT 1 FROM outbox earlier
+        WHERE earlier.key = o.key
+        AND earlier.id < o.id
+        AND earlier.published_at IS NULL
+        AND earlier.next_attempt_at > CURRENT_TIMESTAMP
+        )
+        ORDER BY o.id
+        LIMIT $1
+        FOR UPDATE SKIP LOCKED
+        )
+        RETURNING id, topic, key, payload, attempts
+    ",
+    )
+    .bind(limit)
+    .bind(lease_secs as f64)
+    .fetch_all(db)
+    .await?;
+
+    // RETURNING does not keep the order of the subquery
+    events.sort_by_key(|e| e.id);
+
+    Ok(events)
+}
+
+pub async fn update_outbox_event_published(id: i64, db: impl PgExecutor<'_>) -> anyhow::Result<()> {
+    sqlx::query(
+        "
+        UPDATE outbox
+        SET published_at = CURRENT_TIMESTAMP
+        WHERE id = $1
+    ",
+    )
+    .bind(id)
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn update_outbox_event_failed(
+    id: i64,
+    error: &str,
+    max_backoff_secs: i64,
+    db: impl PgExecutor<'_>,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        "
+        UPDATE outbox
+        SET attempts = attempts + 1,
+            last_error = $2,
+            next_attempt_at = CURRENT_TIMESTAMP
+                + make_interval(secs => LEAST(power(2, attempts), $3))
+        WHERE id = $1
+    ",
+    )
+    .bind(id)
+    .bind(error)
+    .bind(max_backoff_secs as f64)
     .execute(db)
     .await?;
 
@@ -0,0 +1,411 @@
+use anyhow::bail;
+use dotenvy::var;
+use serde_json::to_vec;
+use sha2::{Digest, Sha256};
+use sqlx::{Acquire, prelude::FromRow};
+
+use crate::{
+    api_utils::structs::RequestUpdateProfileEnum,
+    events::UsernameCollisionResolved,
+    policy::username::{collision_candidates, username_skeleton},
+    sql_utils::calls,
+};
+
+// Arbitrary key shared by every replica so only one of them migrates at a time
+const MIGRATION_LOCK_KEY: i64 = 0x7573_6572_5f73_7663;
+
+// Makes usernames unique regardless of case, existing duplicates are renamed right before it
+const CASE_INSENSITIVE_USERNAMES_VERSION: i64 = 5;
+
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+impl Migration {
+    pub fn checksum(&self) -> String {
+        Sha256::digest(self.sql.as_bytes())
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+}
+
+#[derive(FromRow, Debug)]
+struct AppliedMigration {
+    version: i64,
+    name: String,
+    checksum: String,
+}
+
+//Append only, never edit a migration once it has been deployed
+pub static MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial_schema",
+        sql: "
+        CREATE TABLE IF NOT EXISTS users (
+        id TEXT PRIMARY KEY,
+        username TEXT NOT NULL UNIQUE,
+        created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+        );
+
+        CREATE TABLE IF NOT EXISTS friendships (
+        from_user_id TEXT NOT NULL,
+        to_user_id TEXT NOT NULL,
+        created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+
+        CONSTRAINT friendships_pkey PRIMARY KEY (from_user_id, to_user_id),
+        CONSTRAINT fk_from_user FOREIGN KEY (from_user_id) REFERENCES users(id) ON DELETE CASCADE,
+        CONSTRAINT fk_to_user FOREIGN KEY (to_user_id) REFERENCES users(id) ON DELETE CASCADE,
+        CONSTRAINT no_self_friendship CHECK (from_user_id <> to_user_id)
+        );
+
+        CREATE TABLE IF NOT EXISTS blocks (
+        from_user_id TEXT NOT NULL,
+        to_user_id TEXT NOT NULL,
+        created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+
+        CONSTRAINT blocks_pkey PRIMARY KEY (from_user_id, to_user_id),
+        CONSTRAINT fk_from_user FOREIGN KEY (from_user_id) REFERENCES users(id) ON DELETE CASCADE,
+        CONSTRAINT fk_to_user FOREIGN KEY (to_user_id) REFERENCES users(id) ON DELETE CASCADE,
+        CONSTRAINT no_self_block CHECK (from_user_id <> to_user_id)
+        );
+
+        CREATE TABLE IF NOT EXISTS friend_requests (
+        from_user_id TEXT NOT NULL,
+        to_user_id TEXT NOT NULL,
+        state TEXT NOT NULL DEFAULT 'pending', -- pending | accepted | rejected
+        created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+        responded_at TIMESTAMP WITH TIME ZONE,
+
+        CONSTRAINT friend_requestpkey PRIMARY KEY (from_user_id, to_user_id),
+        CONSTRAINT fk_from_user FOREIGN KEY (from_user_id) REFERENCES users(id) ON DELETE CASCADE,
+        CONSTRAINT fk_to_user FOREIGN KEY (to_user_id) REFERENCES users(id) ON DELETE CASCADE,
+        CONSTRAINT no_self_request CHECK (from_user_id <> to_user_id),
+        CONSTRAINT unique_pending_request UNIQUE (from_user_id, to_user_id)
+        );
+    ",
+    },
+    Migration {
+        version: 2,
+        name: "outbox",
+        sql: "
+        CREATE TABLE outbox (
+        id BIGSERIAL PRIMARY KEY,
+        topic TEXT NOT NULL,
+        key TEXT NOT NULL,
+        payload BYTEA NOT NULL,
+        attempts INTEGER NOT NULL DEFAULT 0,
+        last_error TEXT,
+        created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+        next_attempt_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        published_at TIMESTAMP WITH TIME ZONE
+        );
+
+        CREATE INDEX outbox_pending_idx ON outbox (next_attempt_at) WHERE published_at IS NULL;
+    ",
+    },
+    Migration {
+        version: 3,
+        name: "friend_request_history",
+        sql: "
+        ALTER TABLE friend_requests DROP CONSTRAINT unique_pending_request;
+        ALTER TABLE friend_requests DROP CONSTRAINT friend_requestpkey;
+        ALTER TABLE friend_requests ADD COLUMN id BIGSERIAL PRIMARY KEY;
+
+        CREATE UNIQUE INDEX friend_requests_one_pending
+        ON friend_requests (from_user_id, to_user_id) WHERE state = 'pending';
+
+        CREATE INDEX friend_requests_pair_idx
+        ON friend_requests (from_user_id, to_user_id, id DESC);
+    ",
+    },
+    Migration {
+        version: 4,
+        name: "user_profiles",
+        sql: "
+        ALTER TABLE users
+        ADD COLUMN display_name TEXT,
+        ADD COLUMN bio TEXT,
+        ADD COLUMN avatar_url TEXT,
+        ADD COLUMN banner_url TEXT,
+        ADD COLUMN country TEXT;
+    ",
+    },
+    Migration {
+        version: 5,
+        name: "case_insensitive_usernames",
+        sql: "
+        CREATE UNIQUE INDEX users_username_lower ON users (lower(username));
+    ",
+    },
+    Migration {
+        version: 6,
+        name: "username_skeletons",
+        sql: "
+        ALTER TABLE users ADD COLUMN username_skeleton TEXT;
+        CREATE UNIQUE INDEX users_username_skeleton ON users (username_skeleton);
+    ",
+    },
+    Migration {
+        version: 7,
+        name: "username_history",
+        sql: "
+        CREATE TABLE username_history (
+        id BIGSERIAL PRIMARY KEY,
+        user_id TEXT NOT NULL,
+        username TEXT NOT NULL,
+        released_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+        CONSTRAINT fk_user FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+        );
+
+        CREATE INDEX username_history_username
+        ON username_history (lower(username), released_at DESC);
+
+        CREATE INDEX username_history_user
+        ON username_history (user_id, released_at DESC);
+    ",
+    },
+    Migration {
+        version: 8,
+        name: "consumer_offsets",
+        sql: "
+        CREATE TABLE consumer_offsets (
+        consumer TEXT NOT NULL,
+        topic TEXT NOT NULL,
+        next_offset BIGINT NOT NULL,
+        updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+        CONSTRAINT consumer_offsets_pkey PRIMARY KEY (consumer, topic)
+        );
+    ",
+    },
+    Migration {
+        version: 9,
+        name: "user_settings",
+        sql: "
+        CREATE TABLE user_settings (
+        user_id TEXT PRIMARY KEY,
+        friend_requests TEXT NOT NULL DEFAULT 'everyone', -- everyone | friends_of_friends | nobody
+        profile_visibility TEXT NOT NULL DEFAULT 'public', -- public | friends_only
+        friends_visibility TEXT NOT NULL DEFAULT 'public', -- public | friends_only
+        updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+        CONSTRAINT fk_user FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+        );
+    ",
+    },
+    Migration {
+        version: 10,
+        name: "outbox_key_order",
+        sql: "
+        CREATE INDEX outbox_pending_key_idx ON outbox (key, id) WHERE published_at IS NULL;
+    ",
+    },
+    Migration {
+        version: 11,
+        name: "purged_users",
+        sql: "
+        CREATE TABLE purged_users (
+        user_id TEXT PRIMARY KEY,
+        purged_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );
+    ",
+    },
+    Migration {
+        version: 12,
+        name: "recompute_username_skeletons",
+        // Skeletons are now case folded, the backfill after the migrations stores them again
+        sql: "
+        UPDATE users SET username_skeleton = NULL;
+    ",
+    },
+];
+
+pub async fn migrate(db: &sqlx::PgPool) -> anyhow::Result<()> {
+    for pair in MIGRATIONS.windows(2) {
+        if pair[0].version >= pair[1].version {
+            bail!(
+                "Migrations must be sorted by version, found {} before {}",
+                pair[0].version,
+                pair[1].version
+            );
+        }
+    }
+
+    let mut conn = db.acquire().await?;
+
+    sqlx::query(
+        "
+        CREATE TABLE IF NOT EXISTS schema_migrations (
+        version BIGINT PRIMARY KEY,
+        name TEXT NOT NULL,
+        checksum TEXT NOT NULL,
+        applied_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+        )
+    ",
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query("SELECT pg_advisory_lock($1)")
+        .bind(MIGRATION_LOCK_KEY)
+        .execute(&mut *conn)
+        .await?;
+
+    let mut result = apply_pending(&mut conn).await;
+
+    if result.is_ok() {
+        result = backfill_username_skeletons(&mut conn).await;
+    }
+
+    sqlx::query("SELECT pg_advisory_unlock($1)")
+        .bind(MIGRATION_LOCK_KEY)
+        .execute(&mut *conn)
+        .await?;
+
+    result
+}
+
+async fn apply_pending(conn: &mut sqlx::PgConnection) -> anyhow::Result<()> {
+    let applied: Vec<AppliedMigration> = sqlx::query_as(
+        "
+        SELECT version, name, checksum
+        FROM schema_migrations
+        ORDER BY version
+    ",
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    for applied_migration in applied.iter() {
+        let Some(migration) = MIGRATIONS
+            .iter()
+            .find(|m| m.version == applied_migration.version)
+        else {
+            bail!(
+                "Migration {} ({}) is applied but unknown to this build",
+                applied_migration.version,
+                applied_migration.name
+            );
+        };
+
+        if migration.checksum() != applied_migration.checksum {
+            bail!(
+                "Migration {} ({}) was modified after being applied",
+                migration.version,
+                migration.name
+            );
+        }
+    }
+
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|m| !applied.iter().any(|a| a.version == m.version))
+    {
+        let mut tx = conn.begin().await?;
+
+        if migration.version == CASE_INSENSITIVE_USERNAMES_VERSION {
+            rename_case_duplicates(&mut tx).await?;
+        }
+
+        sqlx::raw_sql(migration.sql).execute(&mut *tx).await?;
+
+        sqlx::query(
+            "
+            INSERT INTO schema_migrations (version, name, checksum)
+            VALUES ($1, $2, $3)
+        ",
+        )
+        .bind(migration.version)
+        .bind(migration.name)
+        .bind(migration.checksum())
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        tracing::info!(
+            "Applied migration {} ({})",
+            migration.version,
+            migration.name
+        );
+    }
+
+    Ok(())
+}
+
+// Keeps the oldest of every group of names only differing by case, the others get the same
+// suffixed name a colliding registration would and auth is told about it
+async fn rename_case_duplicates(conn: &mut sqlx::PgConnection) -> anyhow::Result<()> {
+    let resolved_topic = var("USER_USERNAME_RESOLVED_TOPIC")
+        .unwrap_or("user-username-resolved".to_owned())
+        .trim()
+        .to_string();
+
+    let users = calls::get_case_duplicate_users(&mut *conn).await?;
+    let mut kept: Option<&str> = None;
+
+    for (id, username, lowered) in users.iter() {
+        if kept != Some(lowered.as_str()) {
+            kept = Some(lowered.as_str());
+            continue;
+        }
+
+        let mut assigned = None;
+        for candidate in collision_candidates(username, id).take(100) {
+            if calls::get_private_user(&candidate, &mut *conn)
+                .await
+                .is_none()
+            {
+                assigned = Some(candidate);
+                break;
+            }
+        }
+
+        let Some(assigned) = assigned else {
+            bail!("No free username left to rename {username} ({id})");
+        };
+
+        calls::update_user_profile_field(
+            id,
+            &RequestUpdateProfileEnum::Username,
+            Some(&assigned),
+            &mut *conn,
+        )
+        .await?;
+
+        let resolved = UsernameCollisionResolved {
+            user_id: id.clone(),
+            requested_username: username.clone(),
+            assigned_username: assigned.clone(),
+        };
+        calls::insert_outbox_event(&resolved_topic, id, to_vec(&resolved)?, &mut *conn).await?;
+
+        tracing::warn!(
+            "Renamed {username} ({id}) to {assigned}, another user has it in another case"
+        );
+    }
+
+    Ok(())
+}
+
+// Skeletons need the unicode confusables table, so rows older than the column are filled from here
+async fn backfill_username_skeletons(conn: &mut sqlx::PgConnection) -> anyhow::Result<()> {
+    let users = calls::get_users_without_skeleton(&mut *conn).await?;
+
+    for user in users.iter() {
+        let skeleton = username_skeleton(&user.username);
+
+        if let Err(e) = calls::update_user_username_skeleton(&user.id, &skeleton, &mut *conn).await
+        {
+            // Two existing names already look alike, the row stays without skeleton until renamed
+            tracing::warn!("Failed to store the skeleton of {}: {e:?}", user.username);
+        }
+    }
+
+    Ok(())
+}
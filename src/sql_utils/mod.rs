@@ -1,2 +1,2 @@
 pub(crate) mod calls;
-pub(crate) mod init;
+pub(crate) mod migrations;
@@ -0,0 +1,152 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::{TestApp, befriend, usernames};
+use serde_json::json;
+
+#[tokio::test]
+async fn block_removes_friendship_and_requests() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    let bob = app.user("bob-id", "bob").await;
+    befriend(&app, &alice, &bob, "bob", "alice").await;
+
+    let (status, body) = app
+        .post(
+            "/blocks/block",
+            Some(&bob),
+            json!({ "to_user_username": "alice" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["message"], "Block added");
+
+    let (_, friends) = app
+        .get("/friendship/friends?from=0&to=10", Some(&alice))
+        .await;
+    assert!(usernames(&friends).is_empty());
+
+    let (_, sent) = app.get("/friendship/sent?from=0&to=10", Some(&alice)).await;
+    assert!(sent.as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn blocked_user_cannot_request() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    let bob = app.user("bob-id", "bob").await;
+
+    app.post(
+        "/blocks/block",
+        Some(&bob),
+        json!({ "to_user_username": "alice" }),
+    )
+    .await;
+
+    let (status, body) = app
+        .post(
+            "/friendship/request",
+            Some(&alice),
+            json!({ "to_user_username": "bob" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["message"], "User does not exist");
+
+    app.post(
+        "/blocks/unblock",
+        Some(&bob),
+        json!({ "to_user_username": "alice" }),
+    )
+    .await;
+
+    let (status, _) = app
+        .post(
+            "/friendship/request",
+            Some(&alice),
+            json!({ "to_user_username": "bob" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn block_and_unblock_keeps_the_request_cooldown() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    let bob = app.user("bob-id", "bob").await;
+
+    let request = json!({ "to_user_username": "bob" });
+    app.post("/friendship/request", Some(&alice), request.clone())
+        .await;
+    app.post(
+        "/friendship/reject",
+        Some(&bob),
+        json!({ "to_user_username": "alice" }),
+    )
+    .await;
+
+    app.post("/blocks/block", Some(&alice), request.clone())
+        .await;
+    app.post("/blocks/unblock", Some(&alice), request.clone())
+        .await;
+
+    let (status, _) = app.post("/friendship/request", Some(&alice), request).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn block_twice_conflicts() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    app.user("bob-id", "bob").await;
+
+    let body = json!({ "to_user_username": "bob" });
+    app.post("/blocks/block", Some(&alice), body.clone()).await;
+    let (status, body) = app.post("/blocks/block", Some(&alice), body).await;
+
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["message"], "Block already exists");
+}
+
+#[tokio::test]
+async fn unblock_without_block_is_not_found() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    app.user("bob-id", "bob").await;
+
+    let (status, body) = app
+        .post(
+            "/blocks/unblock",
+            Some(&alice),
+            json!({ "to_user_username": "bob" }),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["message"], "Block does not exist");
+}
+
+#[tokio::test]
+async fn blocks_are_listed_newest_first() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    app.user("bob-id", "bob").await;
+    app.user("carol-id", "carol").await;
+
+    for name in ["bob", "carol"] {
+        app.post(
+            "/blocks/block",
+            Some(&alice),
+            json!({ "to_user_username": name }),
+        )
+        .await;
+    }
+
+    let (status, blocks) = app.get("/blocks?from=0&to=10", Some(&alice)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(usernames(&blocks), ["carol", "bob"]);
+
+    let (_, blocks) = app.get("/blocks?from=1&to=10", Some(&alice)).await;
+    assert_eq!(usernames(&blocks), ["bob"]);
+}
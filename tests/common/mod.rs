@@ -0,0 +1,212 @@
+#![allow(dead_code)]
+
+use std::{
+    sync::{Arc, Once},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    Router,
+    body::{Body, to_bytes},
+    http::{Method, Request, StatusCode, header},
+};
+use jsonwebtoken::{EncodingKey, Header, encode};
+use serde::Serialize;
+use serde_json::{Value, json};
+use tower::ServiceExt;
+use user_service::{
+    api_utils::structs::PrivateUser,
+    app::{AppState, router},
+    event_bus::memory::MemoryEventBus,
+    repository::{OutboxRepository, UserRepository, memory::MemoryRepository},
+};
+
+pub const JWT_SECRET: &str = "user-service-test-secret";
+pub const REQUEST_SENT_TOPIC: &str = "test-friendships-request";
+pub const REQUEST_ANSWERED_TOPIC: &str = "test-friendships-answer";
+pub const REQUEST_CANCELLED_TOPIC: &str = "test-friendships-cancel";
+pub const FRIENDSHIP_REMOVED_TOPIC: &str = "test-friendships-removed";
+pub const USER_UPDATED_TOPIC: &str = "test-user-updated";
+pub const USERNAME_RESOLVED_TOPIC: &str = "test-user-username-resolved";
+
+static ENV: Once = Once::new();
+
+#[derive(Serialize)]
+struct TestClaims<'a> {
+    exp: u64,
+    user_id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    username: Option<&'a str>,
+}
+
+pub struct TestApp {
+    pub router: Router,
+    pub repo: Arc<MemoryRepository>,
+    pub bus: MemoryEventBus,
+}
+
+impl TestApp {
+    pub fn new() -> Self {
+        Self::configured(|_| {})
+    }
+
+    pub fn configured(configure: impl FnOnce(&mut AppState)) -> Self {
+        ENV.call_once(|| {
+            // Safe enough, this runs once before any handler reads the env
+            unsafe { std::env::set_var("JWT_SECRET", JWT_SECRET) };
+        });
+
+        let repo = Arc::new(MemoryRepository::new());
+
+        let mut state = AppState {
+            repo: repo.clone(),
+            request_sent_topic: REQUEST_SENT_TOPIC.to_owned(),
+            request_answered_topic: REQUEST_ANSWERED_TOPIC.to_owned(),
+            request_cancelled_topic: REQUEST_CANCELLED_TOPIC.to_owned(),
+            friendship_removed_topic: FRIENDSHIP_REMOVED_TOPIC.to_owned(),
+            user_updated_topic: USER_UPDATED_TOPIC.to_owned(),
+            friend_request_cooldown_secs: 3600,
+            reserved_usernames: vec!["admin".to_owned(), "support".to_owned()],
+            username_change_cooldown_secs: 0,
+            username_hold_secs: 3600,
+            username_resolved_topic: USERNAME_RESOLVED_TOPIC.to_owned(),
+            provision_users_from_claims: false,
+            friendship_path_max_depth: 4,
+            friendship_path_max_friendships: 1000,
+        };
+        configure(&mut state);
+
+        Self {
+            router: router(Arc::new(state)),
+            repo,
+            bus: MemoryEventBus::new(),
+        }
+    }
+
+    /// Inserts a user and returns a valid token for it
+    pub async fn user(&self, id: &str, username: &str) -> String {
+        self.repo
+            .insert_user(
+                PrivateUser {
+                    id: id.to_owned(),
+                    username: username.to_owned(),
+                    created_at: None,
+                },
+                None,
+                None,
+            )
+            .await
+            .expect("Failed to insert test user");
+
+        token(id)
+    }
+
+    pub async fn get(&self, uri: &str, token: Option<&str>) -> (StatusCode, Value) {
+        self.send(Method::GET, uri, token, None).await
+    }
+
+    pub async fn post(&self, uri: &str, token: Option<&str>, body: Value) -> (StatusCode, Value) {
+        self.send(Method::POST, uri, token, Some(body)).await
+    }
+
+    /// Relays the outbox and returns the json payloads published on `topic`
+    pub async fn events(&self, topic: &str) -> Vec<(String, Value)> {
+        self.repo
+            .relay_outbox(&self.bus, i64::MAX, 0, 0)
+            .await
+            .expect("Failed to relay outbox");
+
+        self.bus
+            .events(topic)
+            .into_iter()
+            .map(|event| {
+                let key = String::from_utf8(event.key.unwrap_or_default()).unwrap();
+                let payload = serde_json::from_slice(&event.payload).unwrap();
+                (key, payload)
+            })
+            .collect()
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        uri: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let mut request = Request::builder().method(method).uri(uri);
+
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+        }
+
+        let request = match body {
+            Some(body) => request
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        }
+        .unwrap();
+
+        let response = self.router.clone().oneshot(request).await.unwrap();
+
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = serde_json::from_slice(&bytes)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned()));
+
+        (status, body)
+    }
+}
+
+pub fn token(user_id: &str) -> String {
+    encode_token(user_id, None)
+}
+
+/// Token carrying the username claim used to provision missing users
+pub fn token_with_username(user_id: &str, username: &str) -> String {
+    encode_token(user_id, Some(username))
+}
+
+fn encode_token(user_id: &str, username: Option<&str>) -> String {
+    let exp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        + 3600;
+
+    encode(
+        &Header::default(),
+        &TestClaims {
+            exp,
+            user_id,
+            username,
+        },
+        &EncodingKey::from_secret(JWT_SECRET.as_bytes()),
+    )
+    .unwrap()
+}
+
+/// Sends a friend request from `from` and accepts it as `to`
+pub async fn befriend(app: &TestApp, from: &str, to: &str, to_username: &str, from_username: &str) {
+    app.post(
+        "/friendship/request",
+        Some(from),
+        json!({ "to_user_username": to_username }),
+    )
+    .await;
+    app.post(
+        "/friendship/accept",
+        Some(to),
+        json!({ "to_user_username": from_username }),
+    )
+    .await;
+}
+
+pub fn usernames(list: &Value) -> Vec<&str> {
+    list.as_array()
+        .expect("Expected a list")
+        .iter()
+        .map(|e| e["username"].as_str().unwrap())
+        .collect()
+}
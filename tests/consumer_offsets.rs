@@ -0,0 +1,132 @@
+use futures::StreamExt;
+use user_service::{
+    api_utils::structs::{ConsumerOffset, OutboxEvent, PrivateUser},
+    event_bus::{EventPublisher, EventSubscriber, memory::MemoryEventBus},
+    repository::{
+        ConsumerOffsetRepository, OutboxRepository, UserRepository, is_transient,
+        memory::MemoryRepository,
+    },
+};
+
+fn offset(topic: &str, offset: i64) -> ConsumerOffset {
+    ConsumerOffset {
+        consumer: "user-service".to_owned(),
+        topic: topic.to_owned(),
+        offset,
+    }
+}
+
+#[tokio::test]
+async fn subscription_resumes_at_the_given_offset() {
+    let bus = MemoryEventBus::new();
+
+    for payload in ["a", "b", "c"] {
+        bus.publish("auth-register", "key", payload.as_bytes())
+            .await
+            .unwrap();
+    }
+
+    let mut stream = bus.subscribe("auth-register", 1).await.unwrap();
+
+    let event = stream.next().await.unwrap().unwrap();
+    assert_eq!(event.offset, 1);
+    assert_eq!(event.payload, b"b");
+
+    let event = stream.next().await.unwrap().unwrap();
+    assert_eq!(event.offset, 2);
+}
+
+#[tokio::test]
+async fn offset_is_committed_with_the_user() {
+    let repo = MemoryRepository::new();
+
+    assert_eq!(
+        repo.get_consumer_offset("user-service", "auth-register")
+            .await
+            .unwrap(),
+        None
+    );
+
+    repo.insert_user(
+        PrivateUser {
+            id: "alice-id".to_owned(),
+            username: "alice".to_owned(),
+            created_at: None,
+        },
+        None,
+        Some(offset("auth-register", 4)),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        repo.get_consumer_offset("user-service", "auth-register")
+            .await
+            .unwrap(),
+        Some(4)
+    );
+
+    // A failed insert leaves the offset where it was, so the record is read again
+    let duplicate = repo
+        .insert_user(
+            PrivateUser {
+                id: "alice-id".to_owned(),
+                username: "alice".to_owned(),
+                created_at: None,
+            },
+            None,
+            Some(offset("auth-register", 5)),
+        )
+        .await;
+    assert!(duplicate.is_err());
+    assert_eq!(
+        repo.get_consumer_offset("user-service", "auth-register")
+            .await
+            .unwrap(),
+        Some(4)
+    );
+
+    repo.commit_consumer_offset(offset("auth-register", 0))
+        .await
+        .unwrap();
+    assert_eq!(
+        repo.get_consumer_offset("user-service", "auth-register")
+            .await
+            .unwrap(),
+        Some(0)
+    );
+}
+
+#[tokio::test]
+async fn dead_letter_moves_past_the_record() {
+    let repo = MemoryRepository::new();
+    let bus = MemoryEventBus::new();
+
+    repo.dead_letter(
+        OutboxEvent {
+            topic: "dead-letter".to_owned(),
+            key: "alice-id".to_owned(),
+            payload: b"{}".to_vec(),
+        },
+        offset("auth-register", 8),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        repo.get_consumer_offset("user-service", "auth-register")
+            .await
+            .unwrap(),
+        Some(8)
+    );
+
+    repo.relay_outbox(&bus, 10, 0, 0).await.unwrap();
+    assert_eq!(bus.events("dead-letter").len(), 1);
+}
+
+#[test]
+fn only_database_connection_errors_are_transient() {
+    assert!(is_transient(&sqlx::Error::PoolTimedOut.into()));
+    assert!(!is_transient(&sqlx::Error::RowNotFound.into()));
+    assert!(!is_transient(&anyhow::anyhow!("User already exists")));
+}
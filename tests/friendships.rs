@@ -0,0 +1,864 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::{
+    FRIENDSHIP_REMOVED_TOPIC, REQUEST_ANSWERED_TOPIC, REQUEST_CANCELLED_TOPIC, REQUEST_SENT_TOPIC,
+    TestApp, befriend, usernames,
+};
+use serde_json::json;
+
+#[tokio::test]
+async fn request_is_listed_for_both_users_and_emits_event() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    let bob = app.user("bob-id", "bob").await;
+
+    let (status, body) = app
+        .post(
+            "/friendship/request",
+            Some(&alice),
+            json!({ "to_user_username": "bob" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["message"], "Request created");
+
+    let (status, sent) = app.get("/friendship/sent?from=0&to=10", Some(&alice)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(sent[0]["to_user_username"], "bob");
+    assert_eq!(sent[0]["state"], "pending");
+
+    let (_, received) = app
+        .get("/friendship/received?from=0&to=10", Some(&bob))
+        .await;
+    assert_eq!(received[0]["from_user_username"], "alice");
+
+    let events = app.events(REQUEST_SENT_TOPIC).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].0, "bob-id");
+    assert_eq!(events[0].1["from_username"], "alice");
+}
+
+#[tokio::test]
+async fn duplicate_request_conflicts() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    app.user("bob-id", "bob").await;
+
+    let body = json!({ "to_user_username": "bob" });
+    app.post("/friendship/request", Some(&alice), body.clone())
+        .await;
+    let (status, body) = app.post("/friendship/request", Some(&alice), body).await;
+
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["message"], "Request already exist");
+    assert_eq!(app.events(REQUEST_SENT_TOPIC).await.len(), 1);
+}
+
+#[tokio::test]
+async fn request_to_unknown_user_is_not_found() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+
+    let (status, _) = app
+        .post(
+            "/friendship/request",
+            Some(&alice),
+            json!({ "to_user_username": "ghost" }),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn unknown_caller_is_not_found() {
+    let app = TestApp::new();
+    app.user("bob-id", "bob").await;
+
+    let (status, body) = app
+        .post(
+            "/friendship/request",
+            Some(&common::token("ghost-id")),
+            json!({ "to_user_username": "bob" }),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["message"], "User does not exist");
+}
+
+#[tokio::test]
+async fn missing_or_invalid_token_is_rejected() {
+    let app = TestApp::new();
+    app.user("alice-id", "alice").await;
+
+    let (status, _) = app.get("/friendship/friends?from=0&to=10", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, body) = app
+        .get("/friendship/friends?from=0&to=10", Some("not-a-jwt"))
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "Invalid token");
+}
+
+#[tokio::test]
+async fn accept_creates_friendship_both_ways() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    let bob = app.user("bob-id", "bob").await;
+
+    app.post(
+        "/friendship/request",
+        Some(&alice),
+        json!({ "to_user_username": "bob" }),
+    )
+    .await;
+    let (status, body) = app
+        .post(
+            "/friendship/accept",
+            Some(&bob),
+            json!({ "to_user_username": "alice" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["message"], "Request accepted");
+
+    let (_, friends) = app
+        .get("/friendship/friends?from=0&to=10", Some(&alice))
+        .await;
+    assert_eq!(usernames(&friends), ["bob"]);
+
+    let (_, friends) = app
+        .get("/friendship/friends?from=0&to=10", Some(&bob))
+        .await;
+    assert_eq!(usernames(&friends), ["alice"]);
+
+    let (_, sent) = app.get("/friendship/sent?from=0&to=10", Some(&alice)).await;
+    assert_eq!(sent[0]["state"], "accepted");
+
+    let events = app.events(REQUEST_ANSWERED_TOPIC).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].0, "alice-id");
+    assert_eq!(events[0].1["accepted"], true);
+}
+
+#[tokio::test]
+async fn reject_does_not_create_friendship() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    let bob = app.user("bob-id", "bob").await;
+
+    app.post(
+        "/friendship/request",
+        Some(&alice),
+        json!({ "to_user_username": "bob" }),
+    )
+    .await;
+    let (status, body) = app
+        .post(
+            "/friendship/reject",
+            Some(&bob),
+            json!({ "to_user_username": "alice" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["message"], "Request rejected");
+
+    let (_, friends) = app
+        .get("/friendship/friends?from=0&to=10", Some(&alice))
+        .await;
+    assert!(usernames(&friends).is_empty());
+
+    let events = app.events(REQUEST_ANSWERED_TOPIC).await;
+    assert_eq!(events[0].1["accepted"], false);
+}
+
+#[tokio::test]
+async fn answering_twice_conflicts() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    let bob = app.user("bob-id", "bob").await;
+
+    app.post(
+        "/friendship/request",
+        Some(&alice),
+        json!({ "to_user_username": "bob" }),
+    )
+    .await;
+    let answer = json!({ "to_user_username": "alice" });
+    app.post("/friendship/accept", Some(&bob), answer.clone())
+        .await;
+
+    let (status, body) = app
+        .post("/friendship/reject", Some(&bob), answer.clone())
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["message"], "Friend request already answered");
+
+    let (status, _) = app.post("/friendship/accept", Some(&bob), answer).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn only_the_recipient_can_answer() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    app.user("bob-id", "bob").await;
+
+    app.post(
+        "/friendship/request",
+        Some(&alice),
+        json!({ "to_user_username": "bob" }),
+    )
+    .await;
+    let (status, body) = app
+        .post(
+            "/friendship/accept",
+            Some(&alice),
+            json!({ "to_user_username": "bob" }),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["message"], "Request does not exist");
+}
+
+#[tokio::test]
+async fn pagination_edges() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    for i in 0..3 {
+        let name = format!("user{i}");
+        app.user(&format!("{name}-id"), &name).await;
+        app.post(
+            "/friendship/request",
+            Some(&alice),
+            json!({ "to_user_username": name }),
+        )
+        .await;
+    }
+
+    let (_, page) = app.get("/friendship/sent?from=0&to=2", Some(&alice)).await;
+    assert_eq!(page.as_array().unwrap().len(), 2);
+
+    let (_, page) = app.get("/friendship/sent?from=2&to=10", Some(&alice)).await;
+    assert_eq!(page[0]["to_user_username"], "user2");
+
+    // An empty range still returns a single element
+    let (_, page) = app.get("/friendship/sent?from=1&to=1", Some(&alice)).await;
+    assert_eq!(page[0]["to_user_username"], "user1");
+
+    let (_, page) = app.get("/friendship/sent?from=5&to=10", Some(&alice)).await;
+    assert!(page.as_array().unwrap().is_empty());
+
+    let (status, page) = app.get("/friendship/sent?from=3&to=1", Some(&alice)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(page.is_null());
+
+    let (status, _) = app.get("/friendship/sent", Some(&alice)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn remove_friend_allows_requesting_again() {
+    let app = TestApp::configured(|state| state.friend_request_cooldown_secs = 0);
+    let alice = app.user("alice-id", "alice").await;
+    let bob = app.user("bob-id", "bob").await;
+
+    app.post(
+        "/friendship/request",
+        Some(&alice),
+        json!({ "to_user_username": "bob" }),
+    )
+    .await;
+    app.post(
+        "/friendship/accept",
+        Some(&bob),
+        json!({ "to_user_username": "alice" }),
+    )
+    .await;
+
+    let (status, body) = app
+        .post(
+            "/friendship/remove",
+            Some(&bob),
+            json!({ "to_user_username": "alice" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["message"], "Friendship removed");
+
+    for token in [&alice, &bob] {
+        let (_, friends) = app
+            .get("/friendship/friends?from=0&to=10", Some(token))
+            .await;
+        assert!(usernames(&friends).is_empty());
+    }
+
+    let events = app.events(FRIENDSHIP_REMOVED_TOPIC).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].0, "alice-id");
+    assert_eq!(events[0].1["from_username"], "bob");
+
+    let (status, _) = app
+        .post(
+            "/friendship/request",
+            Some(&alice),
+            json!({ "to_user_username": "bob" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn remove_non_friend_is_not_found() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    app.user("bob-id", "bob").await;
+
+    let (status, body) = app
+        .post(
+            "/friendship/remove",
+            Some(&alice),
+            json!({ "to_user_username": "bob" }),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["message"], "Friendship does not exist");
+}
+
+#[tokio::test]
+async fn cancel_withdraws_pending_request() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    let bob = app.user("bob-id", "bob").await;
+
+    let body = json!({ "to_user_username": "bob" });
+    app.post("/friendship/request", Some(&alice), body.clone())
+        .await;
+
+    let (status, response) = app
+        .post("/friendship/cancel", Some(&alice), body.clone())
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response["message"], "Request cancelled");
+
+    let (_, received) = app
+        .get("/friendship/received?from=0&to=10", Some(&bob))
+        .await;
+    assert!(received.as_array().unwrap().is_empty());
+
+    let events = app.events(REQUEST_CANCELLED_TOPIC).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].0, "bob-id");
+    assert_eq!(events[0].1["from_username"], "alice");
+
+    let (status, _) = app
+        .post("/friendship/cancel", Some(&alice), body.clone())
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    // Cancelling counts like any other answer, requests can not be spammed
+    let (status, _) = app.post("/friendship/request", Some(&alice), body).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn cooldown_applies_to_the_pair_in_both_directions() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    let bob = app.user("bob-id", "bob").await;
+    befriend(&app, &alice, &bob, "bob", "alice").await;
+
+    app.post(
+        "/friendship/remove",
+        Some(&alice),
+        json!({ "to_user_username": "bob" }),
+    )
+    .await;
+
+    // Bob never sent a request, the removal still holds him back
+    let (status, _) = app
+        .post(
+            "/friendship/request",
+            Some(&bob),
+            json!({ "to_user_username": "alice" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn cancel_answered_request_conflicts() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    let bob = app.user("bob-id", "bob").await;
+
+    app.post(
+        "/friendship/request",
+        Some(&alice),
+        json!({ "to_user_username": "bob" }),
+    )
+    .await;
+    app.post(
+        "/friendship/reject",
+        Some(&bob),
+        json!({ "to_user_username": "alice" }),
+    )
+    .await;
+
+    let (status, body) = app
+        .post(
+            "/friendship/cancel",
+            Some(&alice),
+            json!({ "to_user_username": "bob" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["message"], "Friend request already answered");
+}
+
+#[tokio::test]
+async fn rejected_request_is_on_cooldown() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    let bob = app.user("bob-id", "bob").await;
+
+    let request = json!({ "to_user_username": "bob" });
+    app.post("/friendship/request", Some(&alice), request.clone())
+        .await;
+    app.post(
+        "/friendship/reject",
+        Some(&bob),
+        json!({ "to_user_username": "alice" }),
+    )
+    .await;
+
+    let (status, body) = app.post("/friendship/request", Some(&alice), request).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(body["message"], "Request on cooldown");
+    let retry_after = body["retry_after_secs"].as_i64().unwrap();
+    assert!(retry_after > 3500 && retry_after <= 3600);
+}
+
+#[tokio::test]
+async fn rejected_request_can_be_sent_again_after_cooldown() {
+    let app = TestApp::configured(|state| state.friend_request_cooldown_secs = 0);
+    let alice = app.user("alice-id", "alice").await;
+    let bob = app.user("bob-id", "bob").await;
+
+    let request = json!({ "to_user_username": "bob" });
+    app.post("/friendship/request", Some(&alice), request.clone())
+        .await;
+    app.post(
+        "/friendship/reject",
+        Some(&bob),
+        json!({ "to_user_username": "alice" }),
+    )
+    .await;
+
+    let (status, _) = app.post("/friendship/request", Some(&alice), request).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Only the latest request of the pair is listed
+    let (_, received) = app
+        .get("/friendship/received?from=0&to=10", Some(&bob))
+        .await;
+    assert_eq!(received.as_array().unwrap().len(), 1);
+    assert_eq!(received[0]["state"], "pending");
+
+    let (status, _) = app
+        .post(
+            "/friendship/accept",
+            Some(&bob),
+            json!({ "to_user_username": "alice" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn mutual_requests_create_friendship() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    let bob = app.user("bob-id", "bob").await;
+
+    app.post(
+        "/friendship/request",
+        Some(&bob),
+        json!({ "to_user_username": "alice" }),
+    )
+    .await;
+    let (status, body) = app
+        .post(
+            "/friendship/request",
+            Some(&alice),
+            json!({ "to_user_username": "bob" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["message"], "Mutual request, friendship created");
+
+    let (_, friends) = app
+        .get("/friendship/friends?from=0&to=10", Some(&alice))
+        .await;
+    assert_eq!(usernames(&friends), ["bob"]);
+
+    for token in [&alice, &bob] {
+        let (_, sent) = app.get("/friendship/sent?from=0&to=10", Some(token)).await;
+        assert_eq!(sent[0]["state"], "accepted");
+    }
+
+    let mut keys: Vec<String> = app
+        .events(REQUEST_ANSWERED_TOPIC)
+        .await
+        .into_iter()
+        .map(|(key, payload)| {
+            assert_eq!(payload["accepted"], true);
+            key
+        })
+        .collect();
+    keys.sort();
+    assert_eq!(keys, ["alice-id", "bob-id"]);
+
+    // Only bob's original request was announced
+    assert_eq!(app.events(REQUEST_SENT_TOPIC).await.len(), 1);
+}
+
+#[tokio::test]
+async fn request_to_lookalike_of_a_friend_warns() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    let bob = app.user("bob-id", "bob").await;
+    app.user("impostor-id", "b.ob").await;
+
+    app.post(
+        "/friendship/request",
+        Some(&alice),
+        json!({ "to_user_username": "bob" }),
+    )
+    .await;
+    app.post(
+        "/friendship/accept",
+        Some(&bob),
+        json!({ "to_user_username": "alice" }),
+    )
+    .await;
+
+    let (status, body) = app
+        .post(
+            "/friendship/request",
+            Some(&alice),
+            json!({ "to_user_username": "b.ob" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["message"], "Request created");
+    assert_eq!(body["confusable_with"], "bob");
+
+    app.user("carol-id", "carol").await;
+    let (_, body) = app
+        .post(
+            "/friendship/request",
+            Some(&alice),
+            json!({ "to_user_username": "carol" }),
+        )
+        .await;
+    assert!(body.get("warning").is_none());
+}
+
+#[tokio::test]
+async fn friend_list_of_another_user_respects_privacy_and_blocks() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    let bob = app.user("bob-id", "bob").await;
+    let carol = app.user("carol-id", "carol").await;
+    befriend(&app, &alice, &bob, "bob", "alice").await;
+
+    let (status, friends) = app
+        .get("/friendship/of?username=alice&from=0&to=10", Some(&carol))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(usernames(&friends), ["bob"]);
+
+    app.post(
+        "/settings/update",
+        Some(&alice),
+        json!({ "friends_visibility": "friends_only" }),
+    )
+    .await;
+
+    let (status, body) = app
+        .get("/friendship/of?username=alice&from=0&to=10", Some(&carol))
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["message"], "Friend list is only visible to friends");
+
+    let (status, _) = app
+        .get("/friendship/of?username=alice&from=0&to=10", Some(&bob))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    app.post(
+        "/blocks/block",
+        Some(&alice),
+        json!({ "to_user_username": "carol" }),
+    )
+    .await;
+
+    let (status, _) = app
+        .get("/friendship/of?username=alice&from=0&to=10", Some(&carol))
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn mutual_friends_are_shared_by_both_users() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    let bob = app.user("bob-id", "bob").await;
+    let carol = app.user("carol-id", "carol").await;
+    let dave = app.user("dave-id", "dave").await;
+    befriend(&app, &alice, &carol, "carol", "alice").await;
+    befriend(&app, &bob, &carol, "carol", "bob").await;
+    befriend(&app, &alice, &dave, "dave", "alice").await;
+
+    let (status, mutual) = app
+        .get("/friendship/mutual?username=bob&from=0&to=10", Some(&alice))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(usernames(&mutual), ["carol"]);
+
+    let (_, mutual) = app
+        .get("/friendship/mutual?username=dave&from=0&to=10", Some(&bob))
+        .await;
+    assert!(usernames(&mutual).is_empty());
+
+    let (status, _) = app
+        .get(
+            "/friendship/mutual?username=ghost&from=0&to=10",
+            Some(&alice),
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn mutual_friends_respect_a_private_friend_list() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    let bob = app.user("bob-id", "bob").await;
+    let carol = app.user("carol-id", "carol").await;
+    befriend(&app, &alice, &carol, "carol", "alice").await;
+    befriend(&app, &bob, &carol, "carol", "bob").await;
+
+    app.post(
+        "/settings/update",
+        Some(&bob),
+        json!({ "friends_visibility": "friends_only" }),
+    )
+    .await;
+
+    let (status, body) = app
+        .get("/friendship/mutual?username=bob&from=0&to=10", Some(&alice))
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["message"], "Friend list is only visible to friends");
+
+    // Friends of bob still see it
+    let (status, mutual) = app
+        .get("/friendship/mutual?username=bob&from=0&to=10", Some(&carol))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(usernames(&mutual).is_empty());
+}
+
+#[tokio::test]
+async fn suggestions_rank_non_friends_by_mutual_friends() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    let bob = app.user("bob-id", "bob").await;
+    let carol = app.user("carol-id", "carol").await;
+    let dave = app.user("dave-id", "dave").await;
+    let erin = app.user("erin-id", "erin").await;
+    let frank = app.user("frank-id", "frank").await;
+    befriend(&app, &alice, &bob, "bob", "alice").await;
+    befriend(&app, &alice, &carol, "carol", "alice").await;
+    befriend(&app, &bob, &dave, "dave", "bob").await;
+    befriend(&app, &carol, &dave, "dave", "carol").await;
+    befriend(&app, &bob, &erin, "erin", "bob").await;
+    befriend(&app, &bob, &frank, "frank", "bob").await;
+
+    let (status, suggestions) = app
+        .get("/friendship/suggestions?from=0&to=10", Some(&alice))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(usernames(&suggestions), ["dave", "erin", "frank"]);
+    assert_eq!(suggestions[0]["mutual_friends"], 2);
+    assert_eq!(suggestions[1]["mutual_friends"], 1);
+
+    let (_, page) = app
+        .get("/friendship/suggestions?from=1&to=2", Some(&alice))
+        .await;
+    assert_eq!(usernames(&page), ["erin"]);
+
+    // Blocks in either direction and pending requests are left out
+    app.post(
+        "/blocks/block",
+        Some(&erin),
+        json!({ "to_user_username": "alice" }),
+    )
+    .await;
+    app.post(
+        "/friendship/request",
+        Some(&frank),
+        json!({ "to_user_username": "alice" }),
+    )
+    .await;
+
+    let (_, suggestions) = app
+        .get("/friendship/suggestions?from=0&to=10", Some(&alice))
+        .await;
+    assert_eq!(usernames(&suggestions), ["dave"]);
+
+    app.post(
+        "/settings/update",
+        Some(&dave),
+        json!({ "friend_requests": "nobody" }),
+    )
+    .await;
+
+    let (_, suggestions) = app
+        .get("/friendship/suggestions?from=0&to=10", Some(&alice))
+        .await;
+    assert!(usernames(&suggestions).is_empty());
+}
+
+#[tokio::test]
+async fn friendship_path_is_the_shortest_within_the_depth() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    let bob = app.user("bob-id", "bob").await;
+    let carol = app.user("carol-id", "carol").await;
+    let dave = app.user("dave-id", "dave").await;
+    let erin = app.user("erin-id", "erin").await;
+    befriend(&app, &alice, &bob, "bob", "alice").await;
+    befriend(&app, &bob, &carol, "carol", "bob").await;
+    befriend(&app, &carol, &dave, "dave", "carol").await;
+    befriend(&app, &dave, &erin, "erin", "dave").await;
+    befriend(&app, &bob, &erin, "erin", "bob").await;
+
+    let (status, body) = app
+        .get("/friendship/path?username=dave", Some(&alice))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["degrees"], 3);
+    assert_eq!(body["path"][0], "alice");
+    assert_eq!(body["path"][1], "bob");
+    assert_eq!(body["path"][3], "dave");
+
+    let (status, body) = app
+        .get("/friendship/path?username=erin", Some(&alice))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["path"], json!(["alice", "bob", "erin"]));
+
+    let (status, body) = app
+        .get("/friendship/path?username=dave&max_depth=2", Some(&alice))
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(
+        body["message"],
+        "No friendship path found within the maximum depth"
+    );
+
+    let (_, body) = app
+        .get("/friendship/path?username=alice", Some(&alice))
+        .await;
+    assert_eq!(body["degrees"], 0);
+}
+
+#[tokio::test]
+async fn friendship_path_avoids_blocked_and_private_users() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    let bob = app.user("bob-id", "bob").await;
+    let carol = app.user("carol-id", "carol").await;
+    let dave = app.user("dave-id", "dave").await;
+    befriend(&app, &alice, &bob, "bob", "alice").await;
+    befriend(&app, &bob, &carol, "carol", "bob").await;
+    befriend(&app, &carol, &dave, "dave", "carol").await;
+
+    let (status, _) = app
+        .get("/friendship/path?username=dave", Some(&alice))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    app.post(
+        "/settings/update",
+        Some(&carol),
+        json!({ "friends_visibility": "friends_only" }),
+    )
+    .await;
+
+    let (status, _) = app
+        .get("/friendship/path?username=dave", Some(&alice))
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // Friend lists of the caller's own friends stay visible
+    app.post(
+        "/settings/update",
+        Some(&carol),
+        json!({ "friends_visibility": "public" }),
+    )
+    .await;
+    app.post(
+        "/settings/update",
+        Some(&bob),
+        json!({ "friends_visibility": "friends_only" }),
+    )
+    .await;
+
+    let (status, _) = app
+        .get("/friendship/path?username=dave", Some(&alice))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    app.post(
+        "/blocks/block",
+        Some(&carol),
+        json!({ "to_user_username": "alice" }),
+    )
+    .await;
+
+    let (status, _) = app
+        .get("/friendship/path?username=dave", Some(&alice))
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn friendship_path_search_is_capped() {
+    let app = TestApp::configured(|state| state.friendship_path_max_friendships = 2);
+    let alice = app.user("alice-id", "alice").await;
+    let bob = app.user("bob-id", "bob").await;
+    let carol = app.user("carol-id", "carol").await;
+    let dave = app.user("dave-id", "dave").await;
+    befriend(&app, &alice, &bob, "bob", "alice").await;
+    befriend(&app, &alice, &carol, "carol", "alice").await;
+    befriend(&app, &bob, &dave, "dave", "bob").await;
+    befriend(&app, &carol, &dave, "dave", "carol").await;
+
+    let (status, body) = app
+        .get("/friendship/path?username=dave", Some(&alice))
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(
+        body["message"],
+        "Friendship path search stopped at its limit, try a lower max_depth"
+    );
+}
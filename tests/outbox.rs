@@ -0,0 +1,75 @@
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use user_service::{
+    api_utils::structs::{OutboxEvent, PrivateUser},
+    event_bus::{EventPublisher, memory::MemoryEventBus},
+    repository::{OutboxRepository, UserRepository, memory::MemoryRepository},
+};
+
+// Fails the next publishes to `topic`, then behaves like the memory bus
+struct FlakyPublisher {
+    bus: MemoryEventBus,
+    topic: &'static str,
+    failures: Mutex<u32>,
+}
+
+#[async_trait]
+impl EventPublisher for FlakyPublisher {
+    async fn publish(&self, topic: &str, key: &str, payload: &[u8]) -> anyhow::Result<()> {
+        if topic == self.topic {
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                anyhow::bail!("Broker unavailable");
+            }
+        }
+
+        self.bus.publish(topic, key, payload).await
+    }
+}
+
+fn event(topic: &str, key: &str) -> OutboxEvent {
+    OutboxEvent {
+        topic: topic.to_owned(),
+        key: key.to_owned(),
+        payload: b"{}".to_vec(),
+    }
+}
+
+#[tokio::test]
+async fn later_events_wait_for_a_failed_event_with_the_same_key() {
+    let repo = MemoryRepository::new();
+    let publisher = FlakyPublisher {
+        bus: MemoryEventBus::new(),
+        topic: "created",
+        failures: Mutex::new(1),
+    };
+
+    for (id, username) in [("alice-id", "alice"), ("bob-id", "bob")] {
+        let user = PrivateUser {
+            id: id.to_owned(),
+            username: username.to_owned(),
+            created_at: None,
+        };
+        repo.insert_user(user, Some(event("created", id)), None)
+            .await
+            .unwrap();
+    }
+    repo.delete_user("alice-id", event("deleted", "alice-id"), None)
+        .await
+        .unwrap();
+
+    repo.relay_outbox(&publisher, 10, 0, 0).await.unwrap();
+
+    // Only alice's creation failed, her deletion must not overtake it
+    assert!(publisher.bus.events("deleted").is_empty());
+    assert_eq!(publisher.bus.events("created").len(), 1);
+
+    repo.relay_outbox(&publisher, 10, 0, 0).await.unwrap();
+
+    let created = publisher.bus.events("created");
+    assert_eq!(created.len(), 2);
+    assert_eq!(created[1].key.as_deref(), Some("alice-id".as_bytes()));
+    assert_eq!(publisher.bus.events("deleted").len(), 1);
+}
@@ -0,0 +1,151 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::{TestApp, befriend};
+use serde_json::json;
+
+#[tokio::test]
+async fn settings_default_and_update_partially() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+
+    let (status, body) = app.get("/settings", Some(&alice)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["friend_requests"], "everyone");
+    assert_eq!(body["profile_visibility"], "public");
+    assert_eq!(body["friends_visibility"], "public");
+
+    let (status, body) = app
+        .post(
+            "/settings/update",
+            Some(&alice),
+            json!({ "friend_requests": "nobody" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["message"], "Settings updated");
+
+    app.post(
+        "/settings/update",
+        Some(&alice),
+        json!({ "profile_visibility": "friends_only" }),
+    )
+    .await;
+
+    let (_, body) = app.get("/settings", Some(&alice)).await;
+    assert_eq!(body["friend_requests"], "nobody");
+    assert_eq!(body["profile_visibility"], "friends_only");
+    assert_eq!(body["friends_visibility"], "public");
+
+    let (status, _) = app
+        .post(
+            "/settings/update",
+            Some(&alice),
+            json!({ "friend_requests": "sometimes" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn nobody_refuses_every_friend_request() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    let bob = app.user("bob-id", "bob").await;
+
+    app.post(
+        "/settings/update",
+        Some(&bob),
+        json!({ "friend_requests": "nobody" }),
+    )
+    .await;
+
+    let (status, body) = app
+        .post(
+            "/friendship/request",
+            Some(&alice),
+            json!({ "to_user_username": "bob" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(
+        body["message"],
+        "User does not accept friend requests from you"
+    );
+
+    // The setting only covers incoming requests
+    let (status, _) = app
+        .post(
+            "/friendship/request",
+            Some(&bob),
+            json!({ "to_user_username": "alice" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn friends_of_friends_need_a_mutual_friend() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    let bob = app.user("bob-id", "bob").await;
+    let carol = app.user("carol-id", "carol").await;
+
+    app.post(
+        "/settings/update",
+        Some(&carol),
+        json!({ "friend_requests": "friends_of_friends" }),
+    )
+    .await;
+
+    let (status, _) = app
+        .post(
+            "/friendship/request",
+            Some(&alice),
+            json!({ "to_user_username": "carol" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // carol's own requests are not limited by their setting
+    befriend(&app, &alice, &bob, "bob", "alice").await;
+    befriend(&app, &carol, &bob, "bob", "carol").await;
+
+    let (status, _) = app
+        .post(
+            "/friendship/request",
+            Some(&alice),
+            json!({ "to_user_username": "carol" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn friends_only_profiles_are_hidden_from_strangers() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    let bob = app.user("bob-id", "bob").await;
+    let carol = app.user("carol-id", "carol").await;
+    befriend(&app, &alice, &bob, "bob", "alice").await;
+
+    app.post(
+        "/settings/update",
+        Some(&alice),
+        json!({ "profile_visibility": "friends_only" }),
+    )
+    .await;
+
+    for token in [None, Some(carol.as_str())] {
+        let (status, body) = app.get("/?user_username=alice", token).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["message"], "Profile is only visible to friends");
+    }
+
+    let (status, body) = app.get("/?user_username=alice", Some(&bob)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["username"], "alice");
+
+    let (status, _) = app.get("/?user_username=alice", Some(&alice)).await;
+    assert_eq!(status, StatusCode::OK);
+}
@@ -0,0 +1,639 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::{TestApp, USER_UPDATED_TOPIC, USERNAME_RESOLVED_TOPIC, token_with_username};
+use serde_json::json;
+use user_service::{api_utils::structs::OutboxEvent, repository::UserRepository};
+
+#[tokio::test]
+async fn user_info_is_found_by_username() {
+    let app = TestApp::new();
+    app.user("alice-id", "alice").await;
+
+    let (status, body) = app.get("/?user_username=alice", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["username"], "alice");
+
+    let (status, body) = app.get("/?user_username=ghost", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["message"], "User does not exist");
+}
+
+#[tokio::test]
+async fn strangers_only_see_the_public_profile() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    let bob = app.user("bob-id", "bob").await;
+
+    app.post(
+        "/update",
+        Some(&alice),
+        json!({ "query": { "Bio": "Hello", "Country": "fr" } }),
+    )
+    .await;
+
+    for token in [None, Some(bob.as_str())] {
+        let (status, body) = app.get("/?user_username=alice", token).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["username"], "alice");
+        assert!(body.get("id").is_none());
+        assert!(body.get("bio").is_none());
+        assert!(body.get("country").is_none());
+    }
+
+    let (status, _) = app.get("/?user_username=alice", Some("not-a-jwt")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn friends_see_the_friend_profile() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    let bob = app.user("bob-id", "bob").await;
+
+    app.post(
+        "/update",
+        Some(&alice),
+        json!({ "query": { "Bio": "Hello" } }),
+    )
+    .await;
+    app.post(
+        "/friendship/request",
+        Some(&alice),
+        json!({ "to_user_username": "bob" }),
+    )
+    .await;
+    app.post(
+        "/friendship/accept",
+        Some(&bob),
+        json!({ "to_user_username": "alice" }),
+    )
+    .await;
+
+    let (status, body) = app.get("/?user_username=alice", Some(&bob)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["bio"], "Hello");
+    assert!(body["friends_since"].is_string());
+    assert!(body.get("id").is_none());
+}
+
+#[tokio::test]
+async fn blocked_callers_cannot_see_the_profile() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    let bob = app.user("bob-id", "bob").await;
+
+    app.post(
+        "/blocks/block",
+        Some(&alice),
+        json!({ "to_user_username": "bob" }),
+    )
+    .await;
+
+    let (status, body) = app.get("/?user_username=alice", Some(&bob)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["message"], "User does not exist");
+
+    // Blocking someone does not hide them from you
+    let (status, _) = app.get("/?user_username=bob", Some(&alice)).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn me_returns_the_self_profile() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+
+    let (status, body) = app.get("/me", Some(&alice)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["id"], "alice-id");
+    assert_eq!(body["username"], "alice");
+    assert_eq!(body["username_changed_at"], serde_json::Value::Null);
+
+    let (status, _) = app.get("/me", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn update_changes_username() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+
+    let (status, body) = app
+        .post(
+            "/update",
+            Some(&alice),
+            json!({ "query": { "Username": "alicia" } }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["message"], "Profile updated");
+
+    let (status, _) = app.get("/?user_username=alicia", None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // The old name still resolves to alice while it is on hold
+    let (status, body) = app.get("/?user_username=alice", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["username"], "alicia");
+}
+
+#[tokio::test]
+async fn old_username_is_released_after_the_hold() {
+    let app = TestApp::configured(|state| state.username_hold_secs = 0);
+    let alice = app.user("alice-id", "alice").await;
+    let bob = app.user("bob-id", "bob").await;
+
+    app.post(
+        "/update",
+        Some(&alice),
+        json!({ "query": { "Username": "alicia" } }),
+    )
+    .await;
+
+    let (status, _) = app.get("/?user_username=alice", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, _) = app
+        .post(
+            "/update",
+            Some(&bob),
+            json!({ "query": { "Username": "alice" } }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn released_username_is_held_for_its_previous_owner() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    let bob = app.user("bob-id", "bob").await;
+
+    app.post(
+        "/update",
+        Some(&alice),
+        json!({ "query": { "Username": "alicia" } }),
+    )
+    .await;
+
+    let (status, body) = app
+        .post(
+            "/update",
+            Some(&bob),
+            json!({ "query": { "Username": "Alice" } }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(
+        body["message"],
+        "Username was recently released and is on hold"
+    );
+
+    let (status, _) = app
+        .post(
+            "/update",
+            Some(&alice),
+            json!({ "query": { "Username": "alice" } }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn renames_are_on_cooldown() {
+    let app = TestApp::configured(|state| state.username_change_cooldown_secs = 3600);
+    let alice = app.user("alice-id", "alice").await;
+
+    let rename = |username: &str| json!({ "query": { "Username": username } });
+
+    let (status, _) = app.post("/update", Some(&alice), rename("alicia")).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, body) = app.post("/update", Some(&alice), rename("alice2")).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert!(body["retry_after_secs"].as_i64().unwrap() > 0);
+
+    // Other fields and resubmitting the current name are not renames
+    let (status, _) = app
+        .post(
+            "/update",
+            Some(&alice),
+            json!({ "query": { "Username": "alicia", "Bio": "hi" } }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn update_to_taken_username_fails() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    app.user("bob-id", "bob").await;
+
+    for taken in ["bob", "BOB"] {
+        let (status, body) = app
+            .post(
+                "/update",
+                Some(&alice),
+                json!({ "query": { "Username": taken } }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["message"], "Username already taken");
+    }
+
+    let (status, _) = app.get("/?user_username=alice", None).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn update_to_lookalike_username_fails() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    app.user("bob-id", "bob").await;
+
+    // Cyrillic "о" and a zero both look like the latin "o"
+    for lookalike in ["b\u{43e}b", "b0b"] {
+        let (status, body) = app
+            .post(
+                "/update",
+                Some(&alice),
+                json!({ "query": { "Username": lookalike } }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CONFLICT, "{lookalike}");
+        assert_eq!(
+            body["message"],
+            "Username looks too much like an existing one"
+        );
+    }
+
+    // Only other users count, changing the case of your own name is fine
+    let (status, _) = app
+        .post(
+            "/update",
+            Some(&alice),
+            json!({ "query": { "Username": "ALICE" } }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn update_rejects_invalid_and_reserved_usernames() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+
+    let long = "a".repeat(33);
+    let cases = [
+        ("", StatusCode::BAD_REQUEST),
+        ("ab", StatusCode::BAD_REQUEST),
+        ("with space", StatusCode::BAD_REQUEST),
+        ("_alice", StatusCode::BAD_REQUEST),
+        (long.as_str(), StatusCode::BAD_REQUEST),
+        ("Admin", StatusCode::FORBIDDEN),
+        ("ＳＵＰＰＯＲＴ", StatusCode::FORBIDDEN),
+        ("аdmin", StatusCode::FORBIDDEN),
+        ("r00t", StatusCode::FORBIDDEN),
+    ];
+
+    for (username, expected) in cases {
+        let (status, _) = app
+            .post(
+                "/update",
+                Some(&alice),
+                json!({ "query": { "Username": username } }),
+            )
+            .await;
+        assert_eq!(status, expected, "{username}");
+    }
+
+    let (status, _) = app.get("/?user_username=alice", None).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn update_stores_normalized_username() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+
+    // Fullwidth letters fold to ascii under NFKC
+    let (status, _) = app
+        .post(
+            "/update",
+            Some(&alice),
+            json!({ "query": { "Username": " Ａｌｉｃｉａ " } }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (_, body) = app.get("/?user_username=alicia", None).await;
+    assert_eq!(body["username"], "Alicia");
+}
+
+#[tokio::test]
+async fn lookups_normalize_the_username() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    app.user("bob-id", "bob").await;
+
+    let (status, _) = app
+        .post(
+            "/friendship/request",
+            Some(&alice),
+            json!({ "to_user_username": " ＢＯＢ " }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Fullwidth "ａｌｉｃｅ", percent encoded
+    let (status, body) = app
+        .get(
+            "/?user_username=%EF%BD%81%EF%BD%8C%EF%BD%89%EF%BD%83%EF%BD%85",
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["username"], "alice");
+}
+
+#[tokio::test]
+async fn update_for_unknown_caller_is_not_found() {
+    let app = TestApp::new();
+
+    let (status, _) = app
+        .post(
+            "/update",
+            Some(&common::token("ghost-id")),
+            json!({ "query": { "Username": "ghost" } }),
+        )
+        .await;
+
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn update_sets_and_clears_profile_fields() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+
+    let (status, _) = app
+        .post(
+            "/update",
+            Some(&alice),
+            json!({ "query": {
+                "DisplayName": "  Alice  ",
+                "Bio": "Hello\nworld",
+                "Avatar": "https://cdn.example.com/a.png",
+                "Country": "fr",
+            } }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (_, body) = app.get("/me", Some(&alice)).await;
+    assert_eq!(body["display_name"], "Alice");
+    assert_eq!(body["bio"], "Hello\nworld");
+    assert_eq!(body["avatar_url"], "https://cdn.example.com/a.png");
+    assert_eq!(body["banner_url"], serde_json::Value::Null);
+    assert_eq!(body["country"], "FR");
+
+    let (status, _) = app
+        .post(
+            "/update",
+            Some(&alice),
+            json!({ "query": { "DisplayName": "" } }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (_, body) = app.get("/me", Some(&alice)).await;
+    assert_eq!(body["display_name"], serde_json::Value::Null);
+    assert_eq!(body["country"], "FR");
+}
+
+#[tokio::test]
+async fn update_rejects_invalid_profile_fields() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+
+    let cases = [
+        ("DisplayName", "x".repeat(33)),
+        ("Bio", "tab\there".to_owned()),
+        ("Avatar", "http://cdn.example.com/a.png".to_owned()),
+        ("Banner", "javascript:alert(1)".to_owned()),
+        ("Country", "FRA".to_owned()),
+    ];
+
+    for (field, value) in cases {
+        let (status, _) = app
+            .post(
+                "/update",
+                Some(&alice),
+                json!({ "query": { (field): value } }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{field}");
+    }
+
+    let (_, body) = app.get("/?user_username=alice", None).await;
+    assert_eq!(body["display_name"], serde_json::Value::Null);
+}
+
+#[tokio::test]
+async fn profile_changes_emit_user_updated() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+
+    app.post(
+        "/update",
+        Some(&alice),
+        json!({ "query": { "Username": "alicia", "Country": "fr" } }),
+    )
+    .await;
+
+    let events = app.events(USER_UPDATED_TOPIC).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].0, "alice-id");
+    assert_eq!(events[0].1["user_id"], "alice-id");
+
+    let mut changes = events[0].1["changes"].as_array().unwrap().clone();
+    changes.sort_by_key(|c| c["field"].as_str().unwrap().to_owned());
+    assert_eq!(
+        changes,
+        [
+            json!({ "field": "Country", "old": null, "new": "FR" }),
+            json!({ "field": "Username", "old": "alice", "new": "alicia" }),
+        ]
+    );
+
+    // Submitting the current values changes nothing and emits nothing
+    let (status, _) = app
+        .post(
+            "/update",
+            Some(&alice),
+            json!({ "query": { "Username": "alicia", "Country": "FR" } }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(app.events(USER_UPDATED_TOPIC).await.len(), 1);
+}
+
+#[tokio::test]
+async fn deleted_user_is_purged_with_its_relations() {
+    let app = TestApp::new();
+    let alice = app.user("alice-id", "alice").await;
+    let bob = app.user("bob-id", "bob").await;
+    app.user("carol-id", "carol").await;
+
+    app.post(
+        "/friendship/request",
+        Some(&alice),
+        json!({ "to_user_username": "bob" }),
+    )
+    .await;
+    app.post(
+        "/friendship/accept",
+        Some(&bob),
+        json!({ "to_user_username": "alice" }),
+    )
+    .await;
+    app.post(
+        "/friendship/request",
+        Some(&bob),
+        json!({ "to_user_username": "carol" }),
+    )
+    .await;
+
+    app.repo
+        .delete_user(
+            "bob-id",
+            OutboxEvent {
+                topic: "test-user-purged".to_owned(),
+                key: "bob-id".to_owned(),
+                payload: json!({ "user_id": "bob-id", "username": "bob" })
+                    .to_string()
+                    .into_bytes(),
+            },
+            None,
+        )
+        .await
+        .unwrap();
+
+    let (status, _) = app.get("/?user_username=bob", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (_, friends) = app
+        .get("/friendship/friends?from=0&to=10", Some(&alice))
+        .await;
+    assert_eq!(friends, json!([]));
+
+    let carol = common::token("carol-id");
+    let (_, received) = app
+        .get("/friendship/received?from=0&to=10", Some(&carol))
+        .await;
+    assert_eq!(received, json!([]));
+
+    // The name is free again right away, there is no one left to redirect to
+    let (status, _) = app
+        .post(
+            "/update",
+            Some(&alice),
+            json!({ "query": { "Username": "bob" } }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    assert_eq!(app.events("test-user-purged").await.len(), 1);
+}
+
+#[tokio::test]
+async fn missing_user_is_not_provisioned_by_default() {
+    let app = TestApp::new();
+    let dave = token_with_username("dave-id", "dave");
+
+    let (status, _) = app
+        .get("/friendship/friends?from=0&to=10", Some(&dave))
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn missing_user_is_provisioned_from_token_claims() {
+    let app = TestApp::configured(|state| state.provision_users_from_claims = true);
+    let dave = token_with_username("dave-id", "dave");
+
+    let (status, _) = app
+        .get("/friendship/friends?from=0&to=10", Some(&dave))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, body) = app.get("/?user_username=dave", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["username"], "dave");
+
+    // Without the username claim there is nothing to provision from
+    let (status, _) = app
+        .get(
+            "/friendship/friends?from=0&to=10",
+            Some(&common::token("erin-id")),
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn provisioning_a_taken_username_assigns_another_one() {
+    let app = TestApp::configured(|state| state.provision_users_from_claims = true);
+    app.user("alice-id", "alice").await;
+    let impostor = token_with_username("other-id", "alice");
+
+    let (status, _) = app
+        .get("/friendship/friends?from=0&to=10", Some(&impostor))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let events = app.events(USERNAME_RESOLVED_TOPIC).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].0, "other-id");
+    assert_eq!(events[0].1["requested_username"], "alice");
+
+    let assigned = events[0].1["assigned_username"].as_str().unwrap();
+    assert!(assigned.starts_with("alice_"));
+
+    let (status, _) = app.get(&format!("/?user_username={assigned}"), None).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn purged_user_is_not_provisioned_again() {
+    let app = TestApp::configured(|state| state.provision_users_from_claims = true);
+    app.user("dave-id", "dave").await;
+    let dave = token_with_username("dave-id", "dave");
+
+    let event = OutboxEvent {
+        topic: "user-purged".to_owned(),
+        key: "dave-id".to_owned(),
+        payload: Vec::new(),
+    };
+    app.repo.delete_user("dave-id", event, None).await.unwrap();
+
+    let (status, _) = app
+        .get("/friendship/friends?from=0&to=10", Some(&dave))
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, _) = app.get("/?user_username=dave", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(app.repo.get_public_user("dave-id").await.is_none());
+}
+
+#[tokio::test]
+async fn health() {
+    let app = TestApp::new();
+
+    let (status, body) = app.get("/health", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "Long life to the allmighty turbofish");
+}